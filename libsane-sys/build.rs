@@ -1,11 +1,48 @@
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
-    // Tell cargo to tell rustc to link the system bzip2
-    // shared library.
-    println!("cargo:rustc-link-lib=sane");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SANE_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=SANE_STATIC");
 
+    // With dlopen, the library is loaded at runtime and nothing is linked,
+    // so the program starts even where it is missing.
+    let dlopen = env::var_os("CARGO_FEATURE_DLOPEN").is_some();
+    let lib_dir = env::var_os("SANE_LIB_DIR");
+    let statik = env::var_os("SANE_STATIC").is_some_and(|value| value != "0");
+    let kind = if statik { "static" } else { "dylib" };
+
+    // pkg-config tells cargo how to link, unless SANE_LIB_DIR overrides it.
+    // Its include paths are still useful to bindgen either way.
+    let library = pkg_config::Config::new()
+        .statik(statik)
+        .cargo_metadata(!dlopen && lib_dir.is_none())
+        .probe("sane-backends")
+        .ok();
+
+    if !dlopen {
+        if let Some(dir) = &lib_dir {
+            println!(
+                "cargo:rustc-link-search=native={}",
+                Path::new(dir).display()
+            );
+            println!("cargo:rustc-link-lib={}=sane", kind);
+        } else if library.is_none() {
+            // Older installations ship no sane-backends.pc, so try the
+            // default search path.
+            println!("cargo:rustc-link-lib={}=sane", kind);
+        }
+    }
+
+    let include_paths = library.map(|library| library.include_paths);
+    generate_bindings(&include_paths.unwrap_or_default());
+}
+
+/// Regenerate the bindings into $OUT_DIR/bindings.rs instead of using the
+/// checked-in ones.
+#[cfg(feature = "bindgen")]
+fn generate_bindings(include_paths: &[PathBuf]) {
     // Tell cargo to invalidate the built crate whenever the wrapper changes
     println!("cargo:rerun-if-changed=wrapper.h");
 
@@ -16,6 +53,15 @@ fn main() {
         // The input header we would like to generate
         // bindings for.
         .header("wrapper.h")
+        .clang_args(
+            include_paths
+                .iter()
+                .map(|path| format!("-I{}", path.display())),
+        )
+        // The functions are listed in src/functions.rs, so that they can
+        // be loaded at runtime as well.
+        .blacklist_function("sane_.*")
+        .layout_tests(false)
         // Tell cargo to invalidate the built crate whenever any of the
         // included header files changed.
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
@@ -30,3 +76,16 @@ fn main() {
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
 }
+
+/// Use the checked-in bindings, which only describe the layout of 64-bit targets.
+#[cfg(not(feature = "bindgen"))]
+fn generate_bindings(_include_paths: &[PathBuf]) {
+    let width = env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or_default();
+    if width != "64" {
+        panic!(
+            "The checked-in bindings are for 64-bit targets. Enable the `bindgen` feature to \
+             generate bindings for this {}-bit target.",
+            width
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Loads libsane at runtime instead of linking to it. The functions have the same signatures as
+//! the linked ones, and go through a table filled by `load`.
+
+use crate::*;
+use libloading::Library;
+use std::{fmt, sync::OnceLock};
+
+/// Names the library is looked up by, the versioned one first since only development packages
+/// install the unversioned link
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: &[&str] = &["libsane.1.dylib", "libsane.dylib"];
+#[cfg(windows)]
+const LIBRARY_NAMES: &[&str] = &["libsane-1.dll", "libsane.dll"];
+#[cfg(not(any(target_os = "macos", windows)))]
+const LIBRARY_NAMES: &[&str] = &["libsane.so.1", "libsane.so"];
+
+static FUNCTIONS: OnceLock<Functions> = OnceLock::new();
+
+/// The library could not be loaded, or lacks one of the SANE functions.
+#[derive(Debug)]
+pub struct LoadError(String);
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to load libsane: {}", self.0)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+macro_rules! functions {
+    ($(fn $name:ident($($arg:ident: $type:ty),*) $(-> $ret:ty)?;)*) => {
+        struct Functions {
+            $($name: unsafe extern "C" fn($($type),*) $(-> $ret)?,)*
+            _library: Library,
+        }
+
+        impl Functions {
+            unsafe fn new(library: Library) -> Result<Self, libloading::Error> {
+                Ok(Self {
+                    $($name: *library.get(concat!(stringify!($name), "\0").as_bytes())?,)*
+                    _library: library,
+                })
+            }
+        }
+
+        $(
+            /// # Safety
+            /// `load` must have succeeded before.
+            pub unsafe fn $name($($arg: $type),*) $(-> $ret)? {
+                (functions().$name)($($arg),*)
+            }
+        )*
+    };
+}
+
+sane_functions!(functions);
+
+fn functions() -> &'static Functions {
+    FUNCTIONS
+        .get()
+        .expect("libsane_sys::load must succeed before calling SANE functions")
+}
+
+/// Load libsane, unless it is loaded already. Failures aren't remembered, so a later call may
+/// find a library installed in the meantime.
+pub fn load() -> Result<(), LoadError> {
+    if FUNCTIONS.get().is_some() {
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+    for name in LIBRARY_NAMES {
+        let functions = unsafe { Library::new(name).and_then(|library| Functions::new(library)) };
+        match functions {
+            Ok(functions) => {
+                // Another thread may have won the race, which leaves the library loaded just the same
+                let _ = FUNCTIONS.set(functions);
+                return Ok(());
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    Err(LoadError(errors.join("; ")))
+}
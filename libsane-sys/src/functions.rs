@@ -0,0 +1,56 @@
+//! The functions of the SANE 1.0 API. They are listed by hand instead of generated, so that the
+//! `dlopen` feature can call them through a function table instead of linking to them.
+
+#[cfg(not(feature = "dlopen"))]
+use crate::*;
+
+/// Invokes `$callback!` with the signature of every SANE function
+macro_rules! sane_functions {
+    ($callback:ident) => {
+        $callback! {
+            fn sane_init(version_code: *mut SANE_Int, authorize: SANE_Auth_Callback) -> SANE_Status;
+            fn sane_exit();
+            fn sane_get_devices(
+                device_list: *mut *mut *const SANE_Device,
+                local_only: SANE_Bool
+            ) -> SANE_Status;
+            fn sane_open(devicename: SANE_String_Const, handle: *mut SANE_Handle) -> SANE_Status;
+            fn sane_close(handle: SANE_Handle);
+            fn sane_get_option_descriptor(
+                handle: SANE_Handle,
+                option: SANE_Int
+            ) -> *const SANE_Option_Descriptor;
+            fn sane_control_option(
+                handle: SANE_Handle,
+                option: SANE_Int,
+                action: SANE_Action,
+                value: *mut ::std::os::raw::c_void,
+                info: *mut SANE_Int
+            ) -> SANE_Status;
+            fn sane_get_parameters(handle: SANE_Handle, params: *mut SANE_Parameters) -> SANE_Status;
+            fn sane_start(handle: SANE_Handle) -> SANE_Status;
+            fn sane_read(
+                handle: SANE_Handle,
+                data: *mut SANE_Byte,
+                max_length: SANE_Int,
+                length: *mut SANE_Int
+            ) -> SANE_Status;
+            fn sane_cancel(handle: SANE_Handle);
+            fn sane_set_io_mode(handle: SANE_Handle, non_blocking: SANE_Bool) -> SANE_Status;
+            fn sane_get_select_fd(handle: SANE_Handle, fd: *mut SANE_Int) -> SANE_Status;
+            fn sane_strstatus(status: SANE_Status) -> SANE_String_Const;
+        }
+    };
+}
+
+#[cfg(not(feature = "dlopen"))]
+macro_rules! declare {
+    ($(fn $name:ident($($arg:ident: $type:ty),*) $(-> $ret:ty)?;)*) => {
+        extern "C" {
+            $(pub fn $name($($arg: $type),*) $(-> $ret)?;)*
+        }
+    };
+}
+
+#[cfg(not(feature = "dlopen"))]
+sane_functions!(declare);
@@ -2,4 +2,20 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+#[cfg(not(feature = "bindgen"))]
+mod bindings;
+#[cfg(feature = "bindgen")]
+mod bindings {
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+pub use bindings::*;
+
+#[macro_use]
+mod functions;
+#[cfg(not(feature = "dlopen"))]
+pub use functions::*;
+
+#[cfg(feature = "dlopen")]
+mod dynamic;
+#[cfg(feature = "dlopen")]
+pub use dynamic::*;
@@ -0,0 +1,567 @@
+//! Asynchronous reading of a frame, enabled with the `async` cargo feature.
+//!
+//! `AsyncFrame` implements `futures_core::Stream` and `futures_io::AsyncRead`,
+//! so it works with any executor. Tokio users can adapt it with `tokio_util::compat`.
+
+use crate::error::{Result, SaneError};
+use crate::scan::Scan;
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use libsane_sys::*;
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    thread::JoinHandle,
+};
+
+/// Size of the chunks yielded by the `Stream` implementation
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Asynchronous reader for the current frame of a `Scan`, see `Scan::frame_async`.
+///
+/// Uses the backend's select fd when it supports non-blocking I/O, and otherwise
+/// performs the blocking reads on a dedicated thread. Dropping the reader before the end of the
+/// frame cancels the scan.
+pub struct AsyncFrame<'scan, 'device, 'sane> {
+    scan: &'scan mut Scan<'device, 'sane>,
+    reader: Reader,
+    finished: bool,
+}
+
+enum Reader {
+    #[cfg(unix)]
+    Poll(Poller),
+    Thread(Worker),
+}
+
+impl<'scan, 'device, 'sane> AsyncFrame<'scan, 'device, 'sane> {
+    pub(crate) fn new(scan: &'scan mut Scan<'device, 'sane>) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            if scan.set_non_blocking(true)? {
+                if let Some(fd) = scan.select_fd()? {
+                    let poller = Poller::spawn(fd.fd());
+                    return Ok(Self {
+                        scan,
+                        reader: Reader::Poll(poller),
+                        finished: false,
+                    });
+                }
+                scan.set_non_blocking(false)?;
+            }
+        }
+
+        let handle = SendHandle(scan.device().get_handle());
+        let worker = Worker::spawn(move |buf| handle.read(buf));
+        Ok(Self {
+            scan,
+            reader: Reader::Thread(worker),
+            finished: false,
+        })
+    }
+
+    /// Shared implementation of `Stream` and `AsyncRead`, with `Ok(None)` marking the end of the frame
+    fn poll_read_into(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<Option<usize>>> {
+        let scan = &mut self.scan;
+        let result = match &mut self.reader {
+            #[cfg(unix)]
+            Reader::Poll(poller) => poller.poll_read(cx, buf, |buf| scan.read(buf)),
+            Reader::Thread(worker) => worker.poll_read(cx, buf),
+        };
+        if let Poll::Ready(Ok(None)) = result {
+            self.finished = true;
+        }
+        result
+    }
+}
+
+impl Drop for AsyncFrame<'_, '_, '_> {
+    fn drop(&mut self) {
+        // Cancelling also makes a read blocking on the worker thread return, so it can be joined
+        if !self.finished {
+            unsafe { sane_cancel(self.scan.device().get_handle()) };
+        }
+        #[cfg(unix)]
+        if let Reader::Poll(_) = self.reader {
+            let _ = self.scan.set_non_blocking(false);
+        }
+    }
+}
+
+impl Stream for AsyncFrame<'_, '_, '_> {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        match this.poll_read_into(cx, &mut chunk) {
+            Poll::Ready(Ok(Some(length))) => {
+                chunk.truncate(length);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Ok(None)) => Poll::Ready(None),
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for AsyncFrame<'_, '_, '_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut().poll_read_into(cx, buf) {
+            Poll::Ready(Ok(length)) => Poll::Ready(Ok(length.unwrap_or(0))),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Waits for the select fd on a helper thread and wakes the task once it is readable
+#[cfg(unix)]
+struct Poller {
+    wakers: Option<mpsc::Sender<Waker>>,
+    /// Tells the thread to give up waiting, as the fd may never become readable
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl Poller {
+    /// How long a single `poll` call blocks, so the thread notices when it is no longer needed
+    const TIMEOUT_MS: libc::c_int = 100;
+
+    fn spawn(fd: SANE_Int) -> Self {
+        let (wakers, requests) = mpsc::channel::<Waker>();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        let thread = std::thread::spawn(move || {
+            while let Ok(waker) = requests.recv() {
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                loop {
+                    if thread_stopped.load(Ordering::Acquire) {
+                        return;
+                    }
+                    let ready = unsafe { libc::poll(&mut pollfd, 1, Self::TIMEOUT_MS) };
+                    // Errors are reported to the task by its next read
+                    if ready != 0 {
+                        break;
+                    }
+                }
+                waker.wake();
+            }
+        });
+
+        Self {
+            wakers: Some(wakers),
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    fn wait(&self, waker: Waker) {
+        if let Some(wakers) = &self.wakers {
+            let _ = wakers.send(waker);
+        }
+    }
+
+    /// Read with `read`, which doesn't block, and wait for the fd if no data is available yet
+    fn poll_read(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+        read: impl FnOnce(&mut [u8]) -> Result<Option<usize>>,
+    ) -> Poll<Result<Option<usize>>> {
+        match read(buf)? {
+            Some(0) if !buf.is_empty() => {
+                self.wait(cx.waker().clone());
+                Poll::Pending
+            }
+            other => Poll::Ready(Ok(other)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        self.wakers.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// SANE handle moved to the worker thread. The `AsyncFrame` holding the worker
+/// borrows the scan mutably, so no other thread uses the handle meanwhile.
+struct SendHandle(SANE_Handle);
+
+unsafe impl Send for SendHandle {}
+
+impl SendHandle {
+    fn read(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let mut length: SANE_Int = 0;
+        let status = unsafe {
+            sane_read(
+                self.0,
+                buf.as_mut_ptr(),
+                buf.len().min(SANE_Int::MAX as usize) as SANE_Int,
+                &mut length as *mut SANE_Int,
+            )
+        };
+        match SaneError::from_retcode(status) {
+            Ok(()) => Ok(Some(length as usize)),
+            Err(SaneError::EOF) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    result: Option<Result<Option<Vec<u8>>>>,
+    waker: Option<Waker>,
+}
+
+/// Performs blocking reads on a dedicated thread, one per request
+struct Worker {
+    requests: Option<mpsc::Sender<usize>>,
+    shared: Arc<Mutex<Shared>>,
+    in_flight: bool,
+    /// Data of the last chunk that did not fit into the caller's buffer
+    pending: Vec<u8>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Spawn the thread, which calls `read` with a buffer of the requested size. `read` returns
+    /// `Ok(None)` at the end of the frame.
+    fn spawn<F>(mut read: F) -> Self
+    where
+        F: FnMut(&mut [u8]) -> Result<Option<usize>> + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel::<usize>();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let thread_shared = shared.clone();
+
+        let thread = std::thread::spawn(move || {
+            while let Ok(max_length) = receiver.recv() {
+                let mut chunk = vec![0u8; max_length];
+                let result = read(&mut chunk).map(|length| {
+                    length.map(|length| {
+                        chunk.truncate(length);
+                        chunk
+                    })
+                });
+
+                let waker = {
+                    let mut shared = thread_shared.lock().unwrap_or_else(|e| e.into_inner());
+                    shared.result = Some(result);
+                    shared.waker.take()
+                };
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        });
+
+        Self {
+            requests: Some(requests),
+            shared,
+            in_flight: false,
+            pending: Vec::new(),
+            thread: Some(thread),
+        }
+    }
+
+    fn take_pending(&mut self, buf: &mut [u8]) -> usize {
+        let length = self.pending.len().min(buf.len());
+        buf[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+        length
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<Option<usize>>> {
+        if !self.pending.is_empty() {
+            return Poll::Ready(Ok(Some(self.take_pending(buf))));
+        }
+
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            // Backends may return no data without reaching the end of the frame, so read again
+            Some(Ok(Some(chunk))) if chunk.is_empty() && !buf.is_empty() => {
+                self.in_flight = false;
+            }
+            Some(result) => {
+                drop(shared);
+                self.in_flight = false;
+                return Poll::Ready(match result {
+                    Ok(Some(chunk)) => {
+                        self.pending = chunk;
+                        Ok(Some(self.take_pending(buf)))
+                    }
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                });
+            }
+            None => (),
+        }
+        shared.waker = Some(cx.waker().clone());
+        drop(shared);
+
+        if !self.in_flight {
+            if let Some(requests) = &self.requests {
+                let _ = requests.send(buf.len().max(CHUNK_SIZE));
+                self.in_flight = true;
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // The AsyncFrame cancelled the scan beforehand, which makes a pending read return
+        self.requests.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn async_frame_is_send() {
+        assert_send::<AsyncFrame<'static, 'static, 'static>>();
+    }
+
+    /// Sends a message on every wake-up, so tests can wait for them
+    struct Notify(Mutex<mpsc::Sender<()>>);
+
+    impl std::task::Wake for Notify {
+        fn wake(self: Arc<Self>) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    fn waker() -> (Waker, mpsc::Receiver<()>) {
+        let (sender, receiver) = mpsc::channel();
+        (Arc::new(Notify(Mutex::new(sender))).into(), receiver)
+    }
+
+    fn woken(receiver: &mpsc::Receiver<()>) {
+        receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("reader never woke the task");
+    }
+
+    /// Poll the worker until it is ready, waiting for wake-ups in between
+    fn read_worker(worker: &mut Worker, buf: &mut [u8]) -> Result<Option<usize>> {
+        let (waker, wakes) = waker();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match worker.poll_read(&mut cx, buf) {
+                Poll::Ready(result) => return result,
+                Poll::Pending => woken(&wakes),
+            }
+        }
+    }
+
+    #[test]
+    fn worker_reads_chunks_until_eof() {
+        let mut chunks = vec![b"abc".to_vec(), Vec::new(), b"defgh".to_vec()].into_iter();
+        let mut worker = Worker::spawn(move |buf| match chunks.next() {
+            Some(chunk) => {
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(Some(chunk.len()))
+            }
+            None => Ok(None),
+        });
+
+        let mut data = Vec::new();
+        let mut buf = [0u8; 8];
+        while let Some(length) = read_worker(&mut worker, &mut buf).unwrap() {
+            data.extend_from_slice(&buf[..length]);
+        }
+        assert_eq!(data, b"abcdefgh");
+        assert_eq!(read_worker(&mut worker, &mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn worker_reports_errors() {
+        let mut worker = Worker::spawn(|_| Err(SaneError::Jammed));
+        let mut buf = [0u8; 8];
+        assert!(matches!(
+            read_worker(&mut worker, &mut buf),
+            Err(SaneError::Jammed)
+        ));
+    }
+
+    #[test]
+    fn worker_drop_waits_for_the_cancelled_read() {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let (started, reading) = mpsc::channel::<()>();
+        let finished = Arc::new(AtomicBool::new(false));
+        let read_finished = finished.clone();
+        let mut worker = Worker::spawn(move |_| {
+            let _ = started.send(());
+            let _ = cancelled.recv();
+            read_finished.store(true, Ordering::SeqCst);
+            Err(SaneError::Cancelled)
+        });
+
+        let (waker, _wakes) = waker();
+        let mut buf = [0u8; 8];
+        assert!(worker
+            .poll_read(&mut Context::from_waker(&waker), &mut buf)
+            .is_pending());
+        reading
+            .recv_timeout(Duration::from_secs(5))
+            .expect("read never started");
+
+        cancel.send(()).unwrap();
+        drop(worker);
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[cfg(unix)]
+    struct Pipe {
+        read: RawFd,
+        write: Option<RawFd>,
+    }
+
+    #[cfg(unix)]
+    impl Pipe {
+        fn new() -> Self {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            unsafe {
+                let flags = libc::fcntl(fds[0], libc::F_GETFL);
+                libc::fcntl(fds[0], libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+            Pipe {
+                read: fds[0],
+                write: Some(fds[1]),
+            }
+        }
+
+        /// Read like a non-blocking `sane_read`: `Some(0)` without data, `None` at the end
+        fn read(&self, buf: &mut [u8]) -> Result<Option<usize>> {
+            match unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut _, buf.len()) } {
+                0 => Ok(None),
+                n if n > 0 => Ok(Some(n as usize)),
+                _ if std::io::Error::last_os_error().kind() == std::io::ErrorKind::WouldBlock => {
+                    Ok(Some(0))
+                }
+                _ => Err(SaneError::Io),
+            }
+        }
+
+        fn write(&self, data: &[u8]) {
+            let fd = self.write.unwrap();
+            let written = unsafe { libc::write(fd, data.as_ptr() as *const _, data.len()) };
+            assert_eq!(written, data.len() as isize);
+        }
+
+        fn close_write(&mut self) {
+            if let Some(fd) = self.write.take() {
+                unsafe { libc::close(fd) };
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for Pipe {
+        fn drop(&mut self) {
+            self.close_write();
+            unsafe { libc::close(self.read) };
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn poller_waits_for_data_and_eof() {
+        let mut pipe = Pipe::new();
+        let poller = Poller::spawn(pipe.read);
+        let (waker, wakes) = waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0u8; 8];
+
+        assert!(poller
+            .poll_read(&mut cx, &mut buf, |buf| pipe.read(buf))
+            .is_pending());
+        pipe.write(b"xy");
+        woken(&wakes);
+        match poller.poll_read(&mut cx, &mut buf, |buf| pipe.read(buf)) {
+            Poll::Ready(Ok(Some(2))) => assert_eq!(&buf[..2], b"xy"),
+            other => panic!("unexpected read {:?}", other),
+        }
+
+        assert!(poller
+            .poll_read(&mut cx, &mut buf, |buf| pipe.read(buf))
+            .is_pending());
+        pipe.close_write();
+        woken(&wakes);
+        assert!(matches!(
+            poller.poll_read(&mut cx, &mut buf, |buf| pipe.read(buf)),
+            Poll::Ready(Ok(None))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn poller_passes_on_read_errors() {
+        let pipe = Pipe::new();
+        let poller = Poller::spawn(pipe.read);
+        let (waker, _wakes) = waker();
+        let mut buf = [0u8; 8];
+        assert!(matches!(
+            poller.poll_read(&mut Context::from_waker(&waker), &mut buf, |_| Err(
+                SaneError::Jammed
+            )),
+            Poll::Ready(Err(SaneError::Jammed))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn poller_drop_does_not_wait_for_data() {
+        let pipe = Pipe::new();
+        let poller = Poller::spawn(pipe.read);
+        let (waker, _wakes) = waker();
+        let mut buf = [0u8; 8];
+        assert!(poller
+            .poll_read(&mut Context::from_waker(&waker), &mut buf, |buf| pipe
+                .read(buf))
+            .is_pending());
+
+        let start = std::time::Instant::now();
+        drop(poller);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
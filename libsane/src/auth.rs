@@ -0,0 +1,88 @@
+use libsane_sys::*;
+use std::{
+    ffi::CStr,
+    os::raw::c_char,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+/// Credentials callback, called with the name of the resource that requires authorization
+pub(crate) type AuthCallback = dyn Fn(&str) -> Option<(String, String)> + Send + Sync;
+
+/// The C callback has no user data pointer, so the Rust callback lives here
+static CALLBACK: Mutex<Option<Box<AuthCallback>>> = Mutex::new(None);
+
+/// Marks resources whose password must be sent as an MD5 digest, salted with the text that follows
+const MD5_MARKER: &str = "$MD5$";
+
+/// Longest salt and password that take part in the MD5 digest, like scanimage does it
+const MD5_MAX_LEN: usize = 128;
+
+pub(crate) fn register(callback: Box<AuthCallback>) {
+    *CALLBACK.lock().unwrap_or_else(|e| e.into_inner()) = Some(callback);
+}
+
+pub(crate) fn unregister() {
+    CALLBACK.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Ask `callback` for the credentials of `resource`. The password is hashed for resources using
+/// the `$MD5$` challenge.
+pub(crate) fn credentials(resource: &str, callback: &AuthCallback) -> Option<(String, String)> {
+    let (name, salt) = match resource.find(MD5_MARKER) {
+        Some(position) => (
+            &resource[..position],
+            Some(&resource[position + MD5_MARKER.len()..]),
+        ),
+        None => (resource, None),
+    };
+
+    let (user, pass) = callback(name)?;
+    let pass = match salt {
+        Some(salt) => {
+            let mut input = salt.as_bytes()[..salt.len().min(MD5_MAX_LEN)].to_vec();
+            input.extend_from_slice(&pass.as_bytes()[..pass.len().min(MD5_MAX_LEN)]);
+            format!("{}{:x}", MD5_MARKER, md5::compute(&input))
+        }
+        None => pass,
+    };
+    Some((user, pass))
+}
+
+/// Copy `value` into a C buffer of `capacity` bytes, truncating it to leave room for the terminator
+unsafe fn fill(buffer: *mut SANE_Char, capacity: u32, value: &str) {
+    let bytes = value.as_bytes();
+    let length = bytes.len().min(capacity as usize - 1);
+    std::ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buffer, length);
+    *buffer.add(length) = 0;
+}
+
+/// `SANE_Auth_Callback` handed to `sane_init`, which forwards to the registered Rust callback
+pub(crate) unsafe extern "C" fn trampoline(
+    resource: SANE_String_Const,
+    username: *mut SANE_Char,
+    password: *mut SANE_Char,
+) {
+    *username = 0;
+    *password = 0;
+    if resource.is_null() {
+        return;
+    }
+
+    let resource = CStr::from_ptr(resource).to_string_lossy();
+
+    // Unwinding into C is undefined behaviour, so a panicking callback counts as declining
+    let credentials = catch_unwind(AssertUnwindSafe(|| {
+        let callback = CALLBACK.lock().unwrap_or_else(|e| e.into_inner());
+        callback
+            .as_ref()
+            .and_then(|callback| credentials(&resource, callback.as_ref()))
+    }))
+    .ok()
+    .flatten();
+
+    if let Some((user, pass)) = credentials {
+        fill(username, SANE_MAX_USERNAME_LEN, &user);
+        fill(password, SANE_MAX_PASSWORD_LEN, &pass);
+    }
+}
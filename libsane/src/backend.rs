@@ -0,0 +1,1618 @@
+//! Write a SANE backend in Rust.
+//!
+//! A backend implements `Backend` and `BackendDevice`, and `export_backend!` exports them as the
+//! `sane_<name>_*` functions that the `dll` meta backend looks up. Build the crate as a `cdylib`,
+//! install it as `libsane-<name>.so.1` next to the other backends, and list `<name>` in
+//! `dll.conf`. Enable the `dlopen` feature as well, so the backend doesn't link to libsane itself.
+//!
+//! ```ignore
+//! struct Rig;
+//!
+//! impl Backend for Rig {
+//!     type Device = RigDevice;
+//!     // ...
+//! }
+//!
+//! libsane::export_backend!(Rig, "rig");
+//! ```
+
+use crate::{
+    device::{bool_from_word, OptionInfo, ScanParameters, Value},
+    device_list::DeviceInfo,
+    error::{Result, SaneError},
+    option_descriptor::{Constraint, OptionDescriptor, ValueType},
+    version::SaneVersion,
+};
+use libsane_sys::*;
+use std::{
+    convert::TryFrom,
+    ffi::{c_void, CStr, CString},
+    num::NonZeroI32,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, PoisonError, TryLockError,
+    },
+};
+
+#[doc(hidden)]
+pub use libsane_sys as sys;
+
+/// A SANE backend, created by `sane_init` and dropped by `sane_exit`.
+pub trait Backend: Send + Sized + 'static {
+    type Device: BackendDevice;
+
+    /// Build number reported by `sane_init`, along with the SANE version 1.0
+    const BUILD: u16 = 0;
+
+    /// Set up the backend. `authorize` asks the frontend for the credentials of a resource.
+    fn init(authorize: Authorizer) -> Result<Self>;
+
+    /// List the devices this backend can open.
+    fn devices(&mut self, local_only: bool) -> Result<Vec<DeviceInfo>>;
+
+    /// Open the device called `name`, or the first device if `name` is empty.
+    fn open(&mut self, name: &str) -> Result<Self::Device>;
+}
+
+/// A device opened by a `Backend`, dropped by `sane_close`.
+///
+/// Option numbers are the ones frontends see. Option 0 holds the number of options and is
+/// provided by `export_backend!`, so the first descriptor returned by `options` is option 1.
+pub trait BackendDevice: Send + 'static {
+    /// Describe the device's options. Called when the device is opened, and again whenever
+    /// changing an option reports `reload_options`. The `number` fields are ignored.
+    fn options(&self) -> Vec<OptionDescriptor<'_>>;
+
+    /// Current value of `option`.
+    fn get_option(&mut self, option: SANE_Int) -> Result<Value>;
+
+    /// Change `option`, which is active and settable in software. The value has the option's type,
+    /// but may violate its constraint; `OptionDescriptor::snap` along with an `inexact` info is the
+    /// usual answer to that.
+    fn set_option(&mut self, option: SANE_Int, value: Value) -> Result<OptionInfo>;
+
+    /// Pick a value for an option with the `automatic` capability.
+    fn set_option_auto(&mut self, _option: SANE_Int) -> Result<OptionInfo> {
+        Err(SaneError::Unsupported)
+    }
+
+    /// Press the button `option`.
+    fn press_button(&mut self, _option: SANE_Int) -> Result<OptionInfo> {
+        Err(SaneError::Unsupported)
+    }
+
+    /// Parameters of the frame being acquired, or a best guess for the next one before `start`.
+    fn parameters(&mut self) -> Result<ScanParameters>;
+
+    /// Start acquiring a frame.
+    fn start(&mut self) -> Result<()>;
+
+    /// Copy data of the current frame into `buffer`, returning the number of bytes written.
+    /// Return `Err(SaneError::EOF)` once the frame is complete. In non-blocking mode, `Ok(0)`
+    /// means that no data is available yet.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Stop acquiring. A `sane_cancel` arriving while `read` blocks is delivered once it returns,
+    /// see `interrupter` to make it return early.
+    fn cancel(&mut self);
+
+    /// A function that makes a blocking `read` return, e.g. by setting a flag that `read` checks
+    /// or by writing to a pipe it polls. Asked for once when the device is opened. `sane_cancel`
+    /// calls it right away, without waiting for a call in progress, possibly from a signal handler.
+    fn interrupter(&self) -> Option<Interrupter> {
+        None
+    }
+
+    /// Switch `read` between blocking and non-blocking. Only blocking mode is supported by default.
+    fn set_io_mode(&mut self, non_blocking: bool) -> Result<()> {
+        if non_blocking {
+            Err(SaneError::Unsupported)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// File descriptor that becomes readable once image data is available.
+    fn select_fd(&mut self) -> Result<SANE_Int> {
+        Err(SaneError::Unsupported)
+    }
+}
+
+/// Interrupts a blocking `BackendDevice::read`, see `BackendDevice::interrupter`.
+pub type Interrupter = Box<dyn Fn() + Send + Sync>;
+
+/// Asks the frontend for credentials, using the callback it passed to `sane_init`.
+#[derive(Clone, Copy)]
+pub struct Authorizer {
+    callback: SANE_Auth_Callback,
+}
+
+impl Authorizer {
+    /// Ask for a username and password for `resource`. Returns `None` if the frontend has no
+    /// callback. Append `$MD5$` and a salt to the resource to receive an MD5 digest instead of
+    /// the password.
+    pub fn credentials(&self, resource: &str) -> Option<(String, String)> {
+        let callback = self.callback?;
+        let resource = CString::new(resource).ok()?;
+        let mut username = [0 as SANE_Char; SANE_MAX_USERNAME_LEN as usize];
+        let mut password = [0 as SANE_Char; SANE_MAX_PASSWORD_LEN as usize];
+        unsafe {
+            callback(
+                resource.as_ptr(),
+                username.as_mut_ptr(),
+                password.as_mut_ptr(),
+            );
+        }
+        Some((buffer_string(&mut username), buffer_string(&mut password)))
+    }
+}
+
+/// Read a C string from `buffer`, terminating it in case the frontend didn't
+fn buffer_string(buffer: &mut [SANE_Char]) -> String {
+    if let Some(last) = buffer.last_mut() {
+        *last = 0;
+    }
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Run an entry point, turning errors and panics into a status code
+fn status<F: FnOnce() -> Result<()>>(f: F) -> SANE_Status {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => SANE_Status_SANE_STATUS_GOOD,
+        Ok(Err(error)) => error.to_retcode(),
+        // The panic hook has reported the panic already
+        Err(_) => SANE_Status_SANE_STATUS_IO_ERROR,
+    }
+}
+
+const NUM_OPTIONS_TITLE: &[u8] = b"Number of options\0";
+const NUM_OPTIONS_DESC: &[u8] =
+    b"Read-only option that specifies how many options a specific device supports.\0";
+
+/// A C option descriptor, along with the storage its pointers refer to
+struct RawDescriptor {
+    raw: SANE_Option_Descriptor,
+    _strings: Vec<CString>,
+    _string_list: Vec<SANE_String_Const>,
+    _word_list: Vec<SANE_Word>,
+    _range: Option<Box<SANE_Range>>,
+}
+
+// The pointers only refer to the descriptor's own storage
+unsafe impl Send for RawDescriptor {}
+
+/// Copy `string` into `strings`, returning a pointer that stays valid as long as the copy
+fn keep(strings: &mut Vec<CString>, string: Option<&CStr>) -> SANE_String_Const {
+    match string {
+        Some(string) => {
+            let string = CString::from(string);
+            let pointer = string.as_ptr();
+            strings.push(string);
+            pointer
+        }
+        None => ptr::null(),
+    }
+}
+
+impl RawDescriptor {
+    fn new(descriptor: &OptionDescriptor<'_>) -> Self {
+        let mut strings = Vec::new();
+        let name = keep(&mut strings, descriptor.name);
+        let title = keep(&mut strings, descriptor.title);
+        let desc = keep(&mut strings, descriptor.description);
+
+        let mut string_list = Vec::new();
+        let mut word_list = Vec::new();
+        let mut range = None;
+        let (constraint_type, constraint) = match &descriptor.constraint {
+            Constraint::None => (
+                SANE_Constraint_Type_SANE_CONSTRAINT_NONE,
+                SANE_Option_Descriptor__bindgen_ty_1 { range: ptr::null() },
+            ),
+            Constraint::Range { min, max, quant } => {
+                let raw = Box::new(SANE_Range {
+                    min: *min,
+                    max: *max,
+                    quant: quant.map_or(0, NonZeroI32::get),
+                });
+                let constraint = SANE_Option_Descriptor__bindgen_ty_1 { range: &*raw };
+                range = Some(raw);
+                (SANE_Constraint_Type_SANE_CONSTRAINT_RANGE, constraint)
+            }
+            Constraint::FixedRange { min, max, quant } => {
+                let raw = Box::new(SANE_Range {
+                    min: min.0,
+                    max: max.0,
+                    quant: quant.map_or(0, |quant| quant.0),
+                });
+                let constraint = SANE_Option_Descriptor__bindgen_ty_1 { range: &*raw };
+                range = Some(raw);
+                (SANE_Constraint_Type_SANE_CONSTRAINT_RANGE, constraint)
+            }
+            Constraint::List(list) => {
+                // Word lists start with their length
+                word_list.push(list.len() as SANE_Word);
+                word_list.extend_from_slice(list);
+                (
+                    SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST,
+                    SANE_Option_Descriptor__bindgen_ty_1 {
+                        word_list: word_list.as_ptr(),
+                    },
+                )
+            }
+            Constraint::FixedList(list) => {
+                word_list.push(list.len() as SANE_Word);
+                word_list.extend(list.iter().map(|word| word.0));
+                (
+                    SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST,
+                    SANE_Option_Descriptor__bindgen_ty_1 {
+                        word_list: word_list.as_ptr(),
+                    },
+                )
+            }
+            Constraint::StringList(list) => {
+                string_list.extend(list.iter().map(|&entry| keep(&mut strings, Some(entry))));
+                string_list.push(ptr::null());
+                (
+                    SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST,
+                    SANE_Option_Descriptor__bindgen_ty_1 {
+                        string_list: string_list.as_ptr(),
+                    },
+                )
+            }
+        };
+
+        Self {
+            raw: SANE_Option_Descriptor {
+                name,
+                title,
+                desc,
+                type_: descriptor.value_type.into(),
+                unit: descriptor.unit.into(),
+                size: descriptor.size,
+                cap: descriptor.capabilities.into(),
+                constraint_type,
+                constraint,
+            },
+            _strings: strings,
+            _string_list: string_list,
+            _word_list: word_list,
+            _range: range,
+        }
+    }
+
+    /// Descriptor of option 0, which holds the number of options
+    fn count() -> Self {
+        Self {
+            raw: SANE_Option_Descriptor {
+                name: b"\0".as_ptr() as SANE_String_Const,
+                title: NUM_OPTIONS_TITLE.as_ptr() as SANE_String_Const,
+                desc: NUM_OPTIONS_DESC.as_ptr() as SANE_String_Const,
+                type_: SANE_Value_Type_SANE_TYPE_INT,
+                unit: SANE_Unit_SANE_UNIT_NONE,
+                size: std::mem::size_of::<SANE_Int>() as SANE_Int,
+                cap: SANE_CAP_SOFT_DETECT as SANE_Int,
+                constraint_type: SANE_Constraint_Type_SANE_CONSTRAINT_NONE,
+                constraint: SANE_Option_Descriptor__bindgen_ty_1 { range: ptr::null() },
+            },
+            _strings: Vec::new(),
+            _string_list: Vec::new(),
+            _word_list: Vec::new(),
+            _range: None,
+        }
+    }
+}
+
+/// Whether `value` is of type `value_type`
+fn has_type(value: &Value, value_type: ValueType) -> bool {
+    matches!(
+        (value, value_type),
+        (Value::Bool(_), ValueType::Bool)
+            | (Value::Int(_), ValueType::Int)
+            | (Value::Fixed(_), ValueType::Fixed)
+            | (Value::String(_), ValueType::String)
+    )
+}
+
+/// Decode the value a frontend passed to `sane_control_option`, reading at most `size` bytes
+unsafe fn read_value(buffer: *const c_void, size: usize, value_type: ValueType) -> Result<Value> {
+    let buffer = buffer as *const u8;
+    let length = match value_type {
+        // Strings may be shorter than the option, and so may their buffers
+        ValueType::String => (0..size)
+            .find(|&i| *buffer.add(i) == 0)
+            .map(|nul| nul + 1)
+            .ok_or(SaneError::Invalid)?,
+        _ => size,
+    };
+    Value::from_buffer(value_type, std::slice::from_raw_parts(buffer, length))
+}
+
+/// Write `value` into a frontend's buffer of `size` bytes
+unsafe fn write_value(
+    value: &Value,
+    buffer: *mut c_void,
+    size: usize,
+    value_type: ValueType,
+) -> Result<()> {
+    if !has_type(value, value_type) {
+        return Err(SaneError::TypeMismatch);
+    }
+    let bytes = value.to_buffer(size);
+    if bytes.len() > size {
+        return Err(SaneError::ConstraintViolation);
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, bytes.len());
+    Ok(())
+}
+
+/// The state of an open device, guarded by the `Handle`
+struct Open<D> {
+    device: D,
+    /// Option 0 first. Boxed, as a descriptor must stay at its address until the device is closed
+    #[allow(clippy::vec_box)]
+    descriptors: Vec<Box<RawDescriptor>>,
+    /// Number of options, which may be fewer than `descriptors` after a reload
+    count: usize,
+    /// Whether `device.cancel` was called for the pending `sane_cancel`
+    cancel_delivered: bool,
+}
+
+impl<D: BackendDevice> Open<D> {
+    fn new(device: D) -> Self {
+        let mut open = Self {
+            device,
+            descriptors: vec![Box::new(RawDescriptor::count())],
+            count: 1,
+            cancel_delivered: false,
+        };
+        open.reload_options();
+        open
+    }
+
+    /// Describe the options anew, reusing the descriptors' addresses
+    fn reload_options(&mut self) {
+        let options: Vec<RawDescriptor> = self
+            .device
+            .options()
+            .iter()
+            .map(RawDescriptor::new)
+            .collect();
+        self.count = options.len() + 1;
+        for (index, option) in options.into_iter().enumerate() {
+            match self.descriptors.get_mut(index + 1) {
+                Some(descriptor) => **descriptor = option,
+                None => self.descriptors.push(Box::new(option)),
+            }
+        }
+    }
+
+    fn descriptor(&self, option: SANE_Int) -> Result<OptionDescriptor<'_>> {
+        let index = usize::try_from(option)
+            .ok()
+            .filter(|&index| index < self.count)
+            .ok_or(SaneError::Invalid)?;
+        OptionDescriptor::from_descriptor(&self.descriptors[index].raw, option)
+    }
+
+    fn deliver_cancel(&mut self, cancelled: &AtomicBool) {
+        if cancelled.load(Ordering::Acquire) && !self.cancel_delivered {
+            self.cancel_delivered = true;
+            self.device.cancel();
+        }
+    }
+
+    unsafe fn control_option(
+        &mut self,
+        option: SANE_Int,
+        action: SANE_Action,
+        value: *mut c_void,
+    ) -> Result<OptionInfo> {
+        let (value_type, size, capabilities) = {
+            let descriptor = self.descriptor(option)?;
+            (
+                descriptor.value_type,
+                descriptor.size.max(0) as usize,
+                descriptor.capabilities,
+            )
+        };
+
+        if option == 0 {
+            return match action {
+                SANE_Action_SANE_ACTION_GET_VALUE if !value.is_null() => {
+                    *(value as *mut SANE_Int) = self.count as SANE_Int;
+                    Ok(OptionInfo::default())
+                }
+                _ => Err(SaneError::Invalid),
+            };
+        }
+        if capabilities.inactive || matches!(value_type, ValueType::Group) {
+            return Err(SaneError::Invalid);
+        }
+
+        let info = match action {
+            SANE_Action_SANE_ACTION_GET_VALUE => {
+                if value.is_null()
+                    || !capabilities.is_readable()
+                    || matches!(value_type, ValueType::Button)
+                {
+                    return Err(SaneError::Invalid);
+                }
+                let current = self.device.get_option(option)?;
+                write_value(&current, value, size, value_type)?;
+                return Ok(OptionInfo::default());
+            }
+            SANE_Action_SANE_ACTION_SET_VALUE => {
+                if !capabilities.is_settable() {
+                    return Err(SaneError::Invalid);
+                }
+                if matches!(value_type, ValueType::Button) {
+                    self.device.press_button(option)?
+                } else {
+                    if value.is_null() {
+                        return Err(SaneError::Invalid);
+                    }
+                    let info = self
+                        .device
+                        .set_option(option, read_value(value, size, value_type)?)?;
+                    // Tell the frontend which value was stored instead
+                    if info.inexact {
+                        let stored = self.device.get_option(option)?;
+                        write_value(&stored, value, size, value_type)?;
+                    }
+                    info
+                }
+            }
+            SANE_Action_SANE_ACTION_SET_AUTO => {
+                if !capabilities.automatic {
+                    return Err(SaneError::Invalid);
+                }
+                self.device.set_option_auto(option)?
+            }
+            _ => return Err(SaneError::Invalid),
+        };
+
+        if info.reload_options {
+            self.reload_options();
+        }
+        Ok(info)
+    }
+}
+
+/// A device opened through `sane_open`. Its address is the `SANE_Handle`.
+struct Handle<D> {
+    open: Mutex<Open<D>>,
+    /// Set by `sane_cancel` until the next `sane_start`. Frontends may cancel from another
+    /// thread or a signal handler, while a call holds `open`.
+    cancelled: AtomicBool,
+    /// Makes a `read` holding `open` return, so the cancel can be delivered
+    interrupter: Option<Interrupter>,
+}
+
+impl<D: BackendDevice> Handle<D> {
+    unsafe fn from_raw<'a>(handle: SANE_Handle) -> &'a Self {
+        &*(handle as *const Self)
+    }
+
+    /// Lock the device, delivering a `sane_cancel` that arrived while it was busy
+    fn lock(&self) -> MutexGuard<'_, Open<D>> {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        open.deliver_cancel(&self.cancelled);
+        open
+    }
+
+    fn start(&self) -> Result<()> {
+        let mut open = self.lock();
+        self.cancelled.store(false, Ordering::Release);
+        open.cancel_delivered = false;
+        open.device.start()
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut open = self.lock();
+        if self.cancelled.load(Ordering::Acquire) {
+            return Err(SaneError::Cancelled);
+        }
+        let result = open.device.read(buffer);
+        open.deliver_cancel(&self.cancelled);
+        if self.cancelled.load(Ordering::Acquire) {
+            return Err(SaneError::Cancelled);
+        }
+        result.map(|length| length.min(buffer.len()))
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        if let Some(interrupt) = &self.interrupter {
+            interrupt();
+        }
+        // Otherwise the call in progress delivers it
+        let open = match self.open.try_lock() {
+            Ok(open) => Some(open),
+            Err(TryLockError::Poisoned(error)) => Some(error.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        };
+        if let Some(mut open) = open {
+            open.deliver_cancel(&self.cancelled);
+        }
+    }
+}
+
+/// A device list as returned by `sane_get_devices`
+struct DeviceList {
+    _strings: Vec<CString>,
+    _devices: Vec<SANE_Device>,
+    /// Null terminated
+    pointers: Vec<*const SANE_Device>,
+}
+
+// The pointers only refer to the list's own storage
+unsafe impl Send for DeviceList {}
+
+impl DeviceList {
+    fn new(devices: &[DeviceInfo]) -> Result<Self> {
+        let mut strings = Vec::new();
+        let mut string = |value: &str| -> Result<SANE_String_Const> {
+            let value = CString::new(value).map_err(|_| SaneError::Invalid)?;
+            let pointer = value.as_ptr();
+            strings.push(value);
+            Ok(pointer)
+        };
+        let devices = devices
+            .iter()
+            .map(|device| {
+                Ok(SANE_Device {
+                    name: string(&device.name)?,
+                    vendor: string(&device.vendor)?,
+                    model: string(&device.model)?,
+                    type_: string(&device.type_)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut pointers: Vec<*const SANE_Device> =
+            devices.iter().map(|device| device as *const _).collect();
+        pointers.push(ptr::null());
+        Ok(Self {
+            _strings: strings,
+            _devices: devices,
+            pointers,
+        })
+    }
+}
+
+struct State<B> {
+    backend: B,
+    /// The list returned by the last `sane_get_devices`, which stays valid until the next call
+    devices: Option<DeviceList>,
+}
+
+/// Implements the SANE entry points for `export_backend!`.
+#[doc(hidden)]
+pub struct Exported<B: Backend> {
+    state: Mutex<Option<State<B>>>,
+}
+
+#[doc(hidden)]
+impl<B: Backend> Default for Exported<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[doc(hidden)]
+impl<B: Backend> Exported<B> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(None),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, Option<State<B>>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub unsafe fn init(
+        &self,
+        version_code: *mut SANE_Int,
+        authorize: SANE_Auth_Callback,
+    ) -> SANE_Status {
+        status(|| {
+            let backend = B::init(Authorizer {
+                callback: authorize,
+            })?;
+            *self.state() = Some(State {
+                backend,
+                devices: None,
+            });
+            if !version_code.is_null() {
+                *version_code = SaneVersion {
+                    major: SANE_CURRENT_MAJOR as u8,
+                    minor: SANE_CURRENT_MINOR as u8,
+                    build: B::BUILD,
+                }
+                .into();
+            }
+            Ok(())
+        })
+    }
+
+    pub fn exit(&self) {
+        let _ = catch_unwind(AssertUnwindSafe(|| self.state().take()));
+    }
+
+    pub unsafe fn get_devices(
+        &self,
+        device_list: *mut *mut *const SANE_Device,
+        local_only: SANE_Bool,
+    ) -> SANE_Status {
+        status(|| {
+            if device_list.is_null() {
+                return Err(SaneError::Invalid);
+            }
+            let mut state = self.state();
+            let state = state.as_mut().ok_or(SaneError::Invalid)?;
+            let devices = state
+                .backend
+                .devices(local_only != SANE_FALSE as SANE_Bool)?;
+            let devices = state.devices.insert(DeviceList::new(&devices)?);
+            *device_list = devices.pointers.as_mut_ptr();
+            Ok(())
+        })
+    }
+
+    pub unsafe fn open(&self, name: SANE_String_Const, handle: *mut SANE_Handle) -> SANE_Status {
+        status(|| {
+            if handle.is_null() {
+                return Err(SaneError::Invalid);
+            }
+            let name = if name.is_null() {
+                ""
+            } else {
+                CStr::from_ptr(name)
+                    .to_str()
+                    .map_err(|_| SaneError::Invalid)?
+            };
+            let device = self
+                .state()
+                .as_mut()
+                .ok_or(SaneError::Invalid)?
+                .backend
+                .open(name)?;
+            let open = Handle {
+                interrupter: device.interrupter(),
+                open: Mutex::new(Open::new(device)),
+                cancelled: AtomicBool::new(false),
+            };
+            *handle = Box::into_raw(Box::new(open)) as SANE_Handle;
+            Ok(())
+        })
+    }
+
+    pub unsafe fn close(handle: SANE_Handle) {
+        if !handle.is_null() {
+            let handle = Box::from_raw(handle as *mut Handle<B::Device>);
+            let _ = catch_unwind(AssertUnwindSafe(|| drop(handle)));
+        }
+    }
+
+    pub unsafe fn get_option_descriptor(
+        handle: SANE_Handle,
+        option: SANE_Int,
+    ) -> *const SANE_Option_Descriptor {
+        catch_unwind(AssertUnwindSafe(|| {
+            let open = Handle::<B::Device>::from_raw(handle).lock();
+            match usize::try_from(option) {
+                Ok(index) if index < open.count => &open.descriptors[index].raw as *const _,
+                _ => ptr::null(),
+            }
+        }))
+        .unwrap_or(ptr::null())
+    }
+
+    pub unsafe fn control_option(
+        handle: SANE_Handle,
+        option: SANE_Int,
+        action: SANE_Action,
+        value: *mut c_void,
+        info: *mut SANE_Int,
+    ) -> SANE_Status {
+        status(|| {
+            if !info.is_null() {
+                *info = 0;
+            }
+            let option_info = Handle::<B::Device>::from_raw(handle)
+                .lock()
+                .control_option(option, action, value)?;
+            if !info.is_null() {
+                *info = option_info.into();
+            }
+            Ok(())
+        })
+    }
+
+    pub unsafe fn get_parameters(handle: SANE_Handle, params: *mut SANE_Parameters) -> SANE_Status {
+        status(|| {
+            if params.is_null() {
+                return Err(SaneError::Invalid);
+            }
+            let parameters = Handle::<B::Device>::from_raw(handle)
+                .lock()
+                .device
+                .parameters()?;
+            *params = parameters.into();
+            Ok(())
+        })
+    }
+
+    pub unsafe fn start(handle: SANE_Handle) -> SANE_Status {
+        status(|| Handle::<B::Device>::from_raw(handle).start())
+    }
+
+    pub unsafe fn read(
+        handle: SANE_Handle,
+        data: *mut SANE_Byte,
+        max_length: SANE_Int,
+        length: *mut SANE_Int,
+    ) -> SANE_Status {
+        status(|| {
+            if length.is_null() {
+                return Err(SaneError::Invalid);
+            }
+            *length = 0;
+            if data.is_null() || max_length < 0 {
+                return Err(SaneError::Invalid);
+            }
+            let buffer = std::slice::from_raw_parts_mut(data, max_length as usize);
+            *length = Handle::<B::Device>::from_raw(handle).read(buffer)? as SANE_Int;
+            Ok(())
+        })
+    }
+
+    pub unsafe fn cancel(handle: SANE_Handle) {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            Handle::<B::Device>::from_raw(handle).cancel()
+        }));
+    }
+
+    pub unsafe fn set_io_mode(handle: SANE_Handle, non_blocking: SANE_Bool) -> SANE_Status {
+        status(|| {
+            let non_blocking = bool_from_word(non_blocking)?;
+            Handle::<B::Device>::from_raw(handle)
+                .lock()
+                .device
+                .set_io_mode(non_blocking)
+        })
+    }
+
+    pub unsafe fn get_select_fd(handle: SANE_Handle, fd: *mut SANE_Int) -> SANE_Status {
+        status(|| {
+            if fd.is_null() {
+                return Err(SaneError::Invalid);
+            }
+            *fd = Handle::<B::Device>::from_raw(handle)
+                .lock()
+                .device
+                .select_fd()?;
+            Ok(())
+        })
+    }
+}
+
+/// Export `$backend`, which implements `Backend`, as the SANE backend `$name`. The `dll` backend
+/// finds its functions as `sane_<name>_init`, `sane_<name>_open` and so on.
+#[macro_export]
+macro_rules! export_backend {
+    ($backend:ty, $name:literal) => {
+        const _: () = {
+            use $crate::backend::sys::{
+                SANE_Action, SANE_Auth_Callback, SANE_Bool, SANE_Byte, SANE_Device, SANE_Handle,
+                SANE_Int, SANE_Option_Descriptor, SANE_Parameters, SANE_Status, SANE_String_Const,
+            };
+            use $crate::backend::Exported;
+
+            static BACKEND: Exported<$backend> = Exported::new();
+
+            #[export_name = concat!("sane_", $name, "_init")]
+            unsafe extern "C" fn init(
+                version_code: *mut SANE_Int,
+                authorize: SANE_Auth_Callback,
+            ) -> SANE_Status {
+                BACKEND.init(version_code, authorize)
+            }
+
+            #[export_name = concat!("sane_", $name, "_exit")]
+            extern "C" fn exit() {
+                BACKEND.exit()
+            }
+
+            #[export_name = concat!("sane_", $name, "_get_devices")]
+            unsafe extern "C" fn get_devices(
+                device_list: *mut *mut *const SANE_Device,
+                local_only: SANE_Bool,
+            ) -> SANE_Status {
+                BACKEND.get_devices(device_list, local_only)
+            }
+
+            #[export_name = concat!("sane_", $name, "_open")]
+            unsafe extern "C" fn open(
+                name: SANE_String_Const,
+                handle: *mut SANE_Handle,
+            ) -> SANE_Status {
+                BACKEND.open(name, handle)
+            }
+
+            #[export_name = concat!("sane_", $name, "_close")]
+            unsafe extern "C" fn close(handle: SANE_Handle) {
+                Exported::<$backend>::close(handle)
+            }
+
+            #[export_name = concat!("sane_", $name, "_get_option_descriptor")]
+            unsafe extern "C" fn get_option_descriptor(
+                handle: SANE_Handle,
+                option: SANE_Int,
+            ) -> *const SANE_Option_Descriptor {
+                Exported::<$backend>::get_option_descriptor(handle, option)
+            }
+
+            #[export_name = concat!("sane_", $name, "_control_option")]
+            unsafe extern "C" fn control_option(
+                handle: SANE_Handle,
+                option: SANE_Int,
+                action: SANE_Action,
+                value: *mut ::std::ffi::c_void,
+                info: *mut SANE_Int,
+            ) -> SANE_Status {
+                Exported::<$backend>::control_option(handle, option, action, value, info)
+            }
+
+            #[export_name = concat!("sane_", $name, "_get_parameters")]
+            unsafe extern "C" fn get_parameters(
+                handle: SANE_Handle,
+                params: *mut SANE_Parameters,
+            ) -> SANE_Status {
+                Exported::<$backend>::get_parameters(handle, params)
+            }
+
+            #[export_name = concat!("sane_", $name, "_start")]
+            unsafe extern "C" fn start(handle: SANE_Handle) -> SANE_Status {
+                Exported::<$backend>::start(handle)
+            }
+
+            #[export_name = concat!("sane_", $name, "_read")]
+            unsafe extern "C" fn read(
+                handle: SANE_Handle,
+                data: *mut SANE_Byte,
+                max_length: SANE_Int,
+                length: *mut SANE_Int,
+            ) -> SANE_Status {
+                Exported::<$backend>::read(handle, data, max_length, length)
+            }
+
+            #[export_name = concat!("sane_", $name, "_cancel")]
+            unsafe extern "C" fn cancel(handle: SANE_Handle) {
+                Exported::<$backend>::cancel(handle)
+            }
+
+            #[export_name = concat!("sane_", $name, "_set_io_mode")]
+            unsafe extern "C" fn set_io_mode(
+                handle: SANE_Handle,
+                non_blocking: SANE_Bool,
+            ) -> SANE_Status {
+                Exported::<$backend>::set_io_mode(handle, non_blocking)
+            }
+
+            #[export_name = concat!("sane_", $name, "_get_select_fd")]
+            unsafe extern "C" fn get_select_fd(
+                handle: SANE_Handle,
+                fd: *mut SANE_Int,
+            ) -> SANE_Status {
+                Exported::<$backend>::get_select_fd(handle, fd)
+            }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        device::FrameType,
+        fixed::SaneFixed,
+        option_descriptor::{Capabilities, Unit},
+    };
+    use std::{
+        sync::{atomic::AtomicUsize, mpsc, Arc, Condvar},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    const RESOLUTION: SANE_Int = 1;
+    const MODE: SANE_Int = 2;
+    const GAMMA: SANE_Int = 3;
+    /// Only set and seen in hardware
+    const LAMP: SANE_Int = 4;
+    /// Only set in hardware, but visible to software
+    const COVER: SANE_Int = 5;
+    /// Only active in lineart mode
+    const THRESHOLD: SANE_Int = 6;
+
+    const MODES: &[&[u8]] = &[b"Color\0", b"Gray\0", b"Lineart\0"];
+    const GAMMAS: &[SaneFixed] = &[SaneFixed(0x10000), SaneFixed(0x1cccd), SaneFixed(0x23333)];
+    const SOFTWARE: u32 = SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT;
+
+    /// Set while the blocking device waits in `read`
+    static READING: AtomicBool = AtomicBool::new(false);
+    /// Cancels delivered to the blocking device
+    static CANCELS: AtomicUsize = AtomicUsize::new(0);
+
+    fn cstr(bytes: &'static [u8]) -> &'static CStr {
+        CStr::from_bytes_with_nul(bytes).unwrap()
+    }
+
+    struct Stub;
+
+    impl Backend for Stub {
+        type Device = StubDevice;
+        const BUILD: u16 = 7;
+
+        fn init(_authorize: Authorizer) -> Result<Self> {
+            Ok(Stub)
+        }
+
+        fn devices(&mut self, _local_only: bool) -> Result<Vec<DeviceInfo>> {
+            let device = |name: &str| DeviceInfo {
+                name: name.to_owned(),
+                vendor: "Rust".to_owned(),
+                model: "Stub".to_owned(),
+                type_: "virtual device".to_owned(),
+            };
+            Ok(vec![device("stub:0"), device("stub:blocking")])
+        }
+
+        fn open(&mut self, name: &str) -> Result<StubDevice> {
+            match name {
+                "" | "stub:0" => Ok(StubDevice::new(false)),
+                "stub:blocking" => Ok(StubDevice::new(true)),
+                _ => Err(SaneError::Invalid),
+            }
+        }
+    }
+
+    struct StubDevice {
+        resolution: SANE_Word,
+        mode: &'static CStr,
+        gamma: SaneFixed,
+        /// Frames never end, and `read` waits until it is interrupted
+        blocking: bool,
+        /// The rest of the frame
+        data: Vec<u8>,
+        interrupted: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl StubDevice {
+        fn new(blocking: bool) -> Self {
+            Self {
+                resolution: 150,
+                mode: cstr(MODES[0]),
+                gamma: GAMMAS[0],
+                blocking,
+                data: Vec::new(),
+                interrupted: Arc::new((Mutex::new(false), Condvar::new())),
+            }
+        }
+    }
+
+    fn option(
+        name: &'static [u8],
+        value_type: ValueType,
+        unit: Unit,
+        capabilities: u32,
+        constraint: Constraint<'static>,
+    ) -> OptionDescriptor<'static> {
+        OptionDescriptor {
+            number: 0,
+            name: Some(cstr(name)),
+            title: Some(cstr(name)),
+            description: None,
+            value_type,
+            capabilities: Capabilities::from(capabilities as SANE_Int),
+            unit,
+            size: std::mem::size_of::<SANE_Word>() as SANE_Int,
+            constraint,
+        }
+    }
+
+    impl BackendDevice for StubDevice {
+        fn options(&self) -> Vec<OptionDescriptor<'_>> {
+            let mut mode = option(
+                b"mode\0",
+                ValueType::String,
+                Unit::None,
+                SOFTWARE,
+                Constraint::StringList(MODES.iter().map(|&mode| cstr(mode)).collect()),
+            );
+            mode.size = 16;
+            let lineart = self.mode.to_bytes() == b"Lineart";
+
+            vec![
+                option(
+                    b"resolution\0",
+                    ValueType::Int,
+                    Unit::DPI,
+                    SOFTWARE | SANE_CAP_AUTOMATIC,
+                    Constraint::Range {
+                        min: 75,
+                        max: 600,
+                        quant: NonZeroI32::new(75),
+                    },
+                ),
+                mode,
+                option(
+                    b"gamma\0",
+                    ValueType::Fixed,
+                    Unit::None,
+                    SOFTWARE,
+                    Constraint::FixedList(GAMMAS),
+                ),
+                option(
+                    b"lamp\0",
+                    ValueType::Bool,
+                    Unit::None,
+                    SANE_CAP_HARD_SELECT,
+                    Constraint::None,
+                ),
+                option(
+                    b"cover\0",
+                    ValueType::Bool,
+                    Unit::None,
+                    SANE_CAP_HARD_SELECT | SANE_CAP_SOFT_DETECT,
+                    Constraint::None,
+                ),
+                option(
+                    b"threshold\0",
+                    ValueType::Int,
+                    Unit::Percent,
+                    if lineart {
+                        SOFTWARE
+                    } else {
+                        SOFTWARE | SANE_CAP_INACTIVE
+                    },
+                    Constraint::Range {
+                        min: 0,
+                        max: 100,
+                        quant: None,
+                    },
+                ),
+            ]
+        }
+
+        fn get_option(&mut self, option: SANE_Int) -> Result<Value> {
+            match option {
+                RESOLUTION => Ok(Value::Int(Box::new([self.resolution]))),
+                MODE => Ok(Value::String(Box::from(self.mode))),
+                GAMMA => Ok(Value::Fixed(Box::new([self.gamma]))),
+                LAMP | COVER => Ok(Value::Bool(Box::new([true]))),
+                THRESHOLD => Ok(Value::Int(Box::new([50]))),
+                _ => Err(SaneError::Invalid),
+            }
+        }
+
+        fn set_option(&mut self, option: SANE_Int, value: Value) -> Result<OptionInfo> {
+            let snapped = self.options()[option as usize - 1].snap(&value)?;
+            let inexact = snapped != value;
+            match (option, snapped) {
+                (RESOLUTION, Value::Int(words)) => self.resolution = words[0],
+                (MODE, Value::String(mode)) => {
+                    self.mode = MODES
+                        .iter()
+                        .map(|&entry| cstr(entry))
+                        .find(|&entry| *entry == *mode)
+                        .ok_or(SaneError::Invalid)?;
+                    return Ok(OptionInfo {
+                        inexact,
+                        reload_options: true,
+                        reload_params: true,
+                    });
+                }
+                (GAMMA, Value::Fixed(words)) => self.gamma = words[0],
+                _ => return Err(SaneError::Invalid),
+            }
+            Ok(OptionInfo {
+                inexact,
+                reload_options: false,
+                reload_params: option == RESOLUTION,
+            })
+        }
+
+        fn set_option_auto(&mut self, option: SANE_Int) -> Result<OptionInfo> {
+            match option {
+                RESOLUTION => {
+                    self.resolution = 150;
+                    Ok(OptionInfo {
+                        reload_params: true,
+                        ..OptionInfo::default()
+                    })
+                }
+                _ => Err(SaneError::Invalid),
+            }
+        }
+
+        fn parameters(&mut self) -> Result<ScanParameters> {
+            Ok(ScanParameters {
+                format: FrameType::Gray,
+                last_frame: true,
+                lines: Some(2),
+                bytes_per_line: 4,
+                pixels_per_line: 4,
+                depth: 8,
+            })
+        }
+
+        fn start(&mut self) -> Result<()> {
+            *self.interrupted.0.lock().unwrap() = false;
+            if !self.blocking {
+                self.data = (0..8).collect();
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+            if self.blocking {
+                let (interrupted, wakeup) = &*self.interrupted;
+                READING.store(true, Ordering::SeqCst);
+                let _interrupted = wakeup
+                    .wait_while(interrupted.lock().unwrap(), |interrupted| !*interrupted)
+                    .unwrap();
+                READING.store(false, Ordering::SeqCst);
+                return Err(SaneError::Cancelled);
+            }
+            if self.data.is_empty() {
+                return Err(SaneError::EOF);
+            }
+            // Hand out small chunks, so frontends have to read repeatedly
+            let length = buffer.len().min(self.data.len()).min(3);
+            buffer[..length].copy_from_slice(&self.data[..length]);
+            self.data.drain(..length);
+            Ok(length)
+        }
+
+        fn cancel(&mut self) {
+            self.data.clear();
+            if self.blocking {
+                CANCELS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn interrupter(&self) -> Option<Interrupter> {
+            let interrupted = self.interrupted.clone();
+            Some(Box::new(move || {
+                let (interrupted, wakeup) = &*interrupted;
+                *interrupted.lock().unwrap() = true;
+                wakeup.notify_all();
+            }))
+        }
+    }
+
+    crate::export_backend!(Stub, "rusttest");
+
+    extern "C" {
+        fn sane_rusttest_init(
+            version_code: *mut SANE_Int,
+            authorize: SANE_Auth_Callback,
+        ) -> SANE_Status;
+        fn sane_rusttest_get_devices(
+            device_list: *mut *mut *const SANE_Device,
+            local_only: SANE_Bool,
+        ) -> SANE_Status;
+        fn sane_rusttest_open(name: SANE_String_Const, handle: *mut SANE_Handle) -> SANE_Status;
+        fn sane_rusttest_close(handle: SANE_Handle);
+        fn sane_rusttest_get_option_descriptor(
+            handle: SANE_Handle,
+            option: SANE_Int,
+        ) -> *const SANE_Option_Descriptor;
+        fn sane_rusttest_control_option(
+            handle: SANE_Handle,
+            option: SANE_Int,
+            action: SANE_Action,
+            value: *mut c_void,
+            info: *mut SANE_Int,
+        ) -> SANE_Status;
+        fn sane_rusttest_get_parameters(
+            handle: SANE_Handle,
+            params: *mut SANE_Parameters,
+        ) -> SANE_Status;
+        fn sane_rusttest_start(handle: SANE_Handle) -> SANE_Status;
+        fn sane_rusttest_read(
+            handle: SANE_Handle,
+            data: *mut SANE_Byte,
+            max_length: SANE_Int,
+            length: *mut SANE_Int,
+        ) -> SANE_Status;
+        fn sane_rusttest_cancel(handle: SANE_Handle);
+        fn sane_rusttest_set_io_mode(handle: SANE_Handle, non_blocking: SANE_Bool) -> SANE_Status;
+        fn sane_rusttest_get_select_fd(handle: SANE_Handle, fd: *mut SANE_Int) -> SANE_Status;
+    }
+
+    const GOOD: SANE_Status = SANE_Status_SANE_STATUS_GOOD;
+    const INVAL: SANE_Status = SANE_Status_SANE_STATUS_INVAL;
+    const GET: SANE_Action = SANE_Action_SANE_ACTION_GET_VALUE;
+    const SET: SANE_Action = SANE_Action_SANE_ACTION_SET_VALUE;
+    const AUTO: SANE_Action = SANE_Action_SANE_ACTION_SET_AUTO;
+    const INEXACT: SANE_Int = SANE_INFO_INEXACT as SANE_Int;
+    const RELOAD_OPTIONS: SANE_Int = SANE_INFO_RELOAD_OPTIONS as SANE_Int;
+    const RELOAD_PARAMS: SANE_Int = SANE_INFO_RELOAD_PARAMS as SANE_Int;
+
+    /// Initialize the backend, which the tests share. They never call `sane_exit`.
+    fn init() -> SANE_Int {
+        let mut version = 0;
+        assert_eq!(unsafe { sane_rusttest_init(&mut version, None) }, GOOD);
+        version
+    }
+
+    /// A device opened through `sane_open`, closed when dropped
+    struct Opened(SANE_Handle);
+
+    impl Opened {
+        fn new(name: &[u8]) -> Self {
+            init();
+            let mut handle = ptr::null_mut();
+            let name = CStr::from_bytes_with_nul(name).unwrap();
+            assert_eq!(
+                unsafe { sane_rusttest_open(name.as_ptr(), &mut handle) },
+                GOOD
+            );
+            Opened(handle)
+        }
+
+        fn descriptor(&self, option: SANE_Int) -> &SANE_Option_Descriptor {
+            unsafe { sane_rusttest_get_option_descriptor(self.0, option).as_ref() }.unwrap()
+        }
+
+        /// Run `action` on `option` with `buffer`, returning the status and the info flags
+        fn control(
+            &self,
+            option: SANE_Int,
+            action: SANE_Action,
+            buffer: &mut [u8],
+        ) -> (SANE_Status, SANE_Int) {
+            let mut info = -1;
+            let status = unsafe {
+                sane_rusttest_control_option(
+                    self.0,
+                    option,
+                    action,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    &mut info,
+                )
+            };
+            (status, info)
+        }
+
+        /// Like `control`, for options holding a single word. Also returns the word afterwards.
+        fn word(
+            &self,
+            option: SANE_Int,
+            action: SANE_Action,
+            word: SANE_Word,
+        ) -> (SANE_Status, SANE_Int, SANE_Word) {
+            let mut word = word;
+            let mut info = -1;
+            let status = unsafe {
+                sane_rusttest_control_option(
+                    self.0,
+                    option,
+                    action,
+                    &mut word as *mut SANE_Word as *mut c_void,
+                    &mut info,
+                )
+            };
+            (status, info, word)
+        }
+
+        fn start(&self) -> SANE_Status {
+            unsafe { sane_rusttest_start(self.0) }
+        }
+
+        fn read(&self, buffer: &mut [u8]) -> (SANE_Status, SANE_Int) {
+            read(self.0, buffer)
+        }
+    }
+
+    impl Drop for Opened {
+        fn drop(&mut self) {
+            unsafe { sane_rusttest_close(self.0) };
+        }
+    }
+
+    fn read(handle: SANE_Handle, buffer: &mut [u8]) -> (SANE_Status, SANE_Int) {
+        let mut length = -1;
+        let status = unsafe {
+            sane_rusttest_read(
+                handle,
+                buffer.as_mut_ptr(),
+                buffer.len() as SANE_Int,
+                &mut length,
+            )
+        };
+        (status, length)
+    }
+
+    unsafe fn string<'a>(pointer: SANE_String_Const) -> &'a [u8] {
+        CStr::from_ptr(pointer).to_bytes()
+    }
+
+    #[test]
+    fn reports_the_version_and_devices() {
+        assert_eq!(
+            SaneVersion::from(init()),
+            SaneVersion {
+                major: 1,
+                minor: 0,
+                build: 7
+            }
+        );
+
+        let mut list: *mut *const SANE_Device = ptr::null_mut();
+        assert_eq!(
+            unsafe { sane_rusttest_get_devices(&mut list, SANE_TRUE as SANE_Bool) },
+            GOOD
+        );
+        let names: Vec<&[u8]> = (0..)
+            .map(|index| unsafe { *list.add(index) })
+            .take_while(|device| !device.is_null())
+            .map(|device| unsafe { string((*device).name) })
+            .collect();
+        assert_eq!(names, [&b"stub:0"[..], b"stub:blocking"]);
+
+        let mut handle = ptr::null_mut();
+        let name = cstr(b"stub:missing\0");
+        assert_eq!(
+            unsafe { sane_rusttest_open(name.as_ptr(), &mut handle) },
+            INVAL
+        );
+        assert!(handle.is_null());
+    }
+
+    #[test]
+    fn describes_options_and_their_constraints() {
+        let device = Opened::new(b"stub:0\0");
+
+        let count = device.descriptor(0);
+        assert_eq!(unsafe { string(count.title) }, b"Number of options");
+        assert_eq!(count.type_, SANE_Value_Type_SANE_TYPE_INT);
+        assert_eq!(count.cap, SANE_CAP_SOFT_DETECT as SANE_Int);
+        assert_eq!(device.word(0, GET, 0), (GOOD, 0, 7));
+
+        let resolution = device.descriptor(RESOLUTION);
+        assert_eq!(unsafe { string(resolution.name) }, b"resolution");
+        assert!(resolution.desc.is_null());
+        assert_eq!(resolution.type_, SANE_Value_Type_SANE_TYPE_INT);
+        assert_eq!(resolution.unit, SANE_Unit_SANE_UNIT_DPI);
+        assert_eq!(resolution.size, 4);
+        assert_eq!(resolution.cap, (SOFTWARE | SANE_CAP_AUTOMATIC) as SANE_Int);
+        assert_eq!(
+            resolution.constraint_type,
+            SANE_Constraint_Type_SANE_CONSTRAINT_RANGE
+        );
+        let range = unsafe { *resolution.constraint.range };
+        assert_eq!((range.min, range.max, range.quant), (75, 600, 75));
+
+        let mode = device.descriptor(MODE);
+        assert_eq!(mode.size, 16);
+        assert_eq!(
+            mode.constraint_type,
+            SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST
+        );
+        let modes: Vec<&[u8]> = (0..)
+            .map(|index| unsafe { *mode.constraint.string_list.add(index) })
+            .take_while(|entry| !entry.is_null())
+            .map(|entry| unsafe { string(entry) })
+            .collect();
+        assert_eq!(modes, [&b"Color"[..], b"Gray", b"Lineart"]);
+
+        let gamma = device.descriptor(GAMMA);
+        assert_eq!(gamma.type_, SANE_Value_Type_SANE_TYPE_FIXED);
+        assert_eq!(
+            gamma.constraint_type,
+            SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST
+        );
+        let words = unsafe { std::slice::from_raw_parts(gamma.constraint.word_list, 4) };
+        assert_eq!(words, [3, 0x10000, 0x1cccd, 0x23333]);
+
+        let lamp = device.descriptor(LAMP);
+        assert_eq!(lamp.cap, SANE_CAP_HARD_SELECT as SANE_Int);
+        assert_eq!(
+            lamp.constraint_type,
+            SANE_Constraint_Type_SANE_CONSTRAINT_NONE
+        );
+
+        // The descriptors read back into what the device described
+        let threshold =
+            OptionDescriptor::from_descriptor(device.descriptor(THRESHOLD), THRESHOLD).unwrap();
+        assert_eq!(threshold.unit, Unit::Percent);
+        assert!(threshold.capabilities.inactive);
+        assert!(matches!(
+            threshold.constraint,
+            Constraint::Range {
+                min: 0,
+                max: 100,
+                quant: None
+            }
+        ));
+
+        for &option in &[7, -1] {
+            assert!(unsafe { sane_rusttest_get_option_descriptor(device.0, option) }.is_null());
+        }
+    }
+
+    #[test]
+    fn gets_and_sets_values_with_info_flags() {
+        let device = Opened::new(b"stub:0\0");
+        assert_eq!(device.word(RESOLUTION, GET, 0), (GOOD, 0, 150));
+        assert_eq!(
+            device.word(RESOLUTION, SET, 300),
+            (GOOD, RELOAD_PARAMS, 300)
+        );
+        // The value stored instead is written back
+        assert_eq!(
+            device.word(RESOLUTION, SET, 310),
+            (GOOD, INEXACT | RELOAD_PARAMS, 300)
+        );
+        assert_eq!(device.word(RESOLUTION, GET, 0), (GOOD, 0, 300));
+        let (status, info, _) = device.word(RESOLUTION, AUTO, 0);
+        assert_eq!((status, info), (GOOD, RELOAD_PARAMS));
+        assert_eq!(device.word(RESOLUTION, GET, 0), (GOOD, 0, 150));
+
+        assert_eq!(
+            device.word(GAMMA, SET, SaneFixed::from_f64(1.75).0),
+            (GOOD, INEXACT, 0x1cccd)
+        );
+
+        let mut mode = [0u8; 16];
+        mode[..8].copy_from_slice(b"lineart\0");
+        assert_eq!(
+            device.control(MODE, SET, &mut mode),
+            (GOOD, INEXACT | RELOAD_OPTIONS | RELOAD_PARAMS)
+        );
+        assert_eq!(&mode[..8], b"Lineart\0");
+        assert_eq!(device.control(MODE, AUTO, &mut mode), (INVAL, 0));
+
+        // Reloading the options activated the threshold, at the same address
+        assert_eq!(device.descriptor(THRESHOLD).cap, SOFTWARE as SANE_Int);
+        assert_eq!(device.word(THRESHOLD, GET, 0), (GOOD, 0, 50));
+    }
+
+    #[test]
+    fn rejects_options_software_cannot_access() {
+        let device = Opened::new(b"stub:0\0");
+        assert_eq!(device.word(LAMP, GET, 0).0, INVAL);
+        assert_eq!(
+            device.word(COVER, GET, 0),
+            (GOOD, 0, SANE_TRUE as SANE_Word)
+        );
+        assert_eq!(device.word(COVER, SET, SANE_FALSE as SANE_Word).0, INVAL);
+        assert_eq!(device.word(THRESHOLD, GET, 0).0, INVAL);
+        assert_eq!(device.word(0, SET, 3).0, INVAL);
+        assert_eq!(device.word(7, GET, 0).0, INVAL);
+        assert_eq!(device.word(-1, GET, 0).0, INVAL);
+        assert_eq!(device.word(RESOLUTION, 3, 0).0, INVAL);
+        let status = unsafe {
+            sane_rusttest_control_option(
+                device.0,
+                RESOLUTION,
+                GET,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, INVAL);
+    }
+
+    #[test]
+    fn reads_frames_until_eof() {
+        let device = Opened::new(b"stub:0\0");
+        assert_eq!(device.start(), GOOD);
+
+        let mut params = SANE_Parameters {
+            format: 0,
+            last_frame: 0,
+            bytes_per_line: 0,
+            pixels_per_line: 0,
+            lines: 0,
+            depth: 0,
+        };
+        assert_eq!(
+            unsafe { sane_rusttest_get_parameters(device.0, &mut params) },
+            GOOD
+        );
+        assert_eq!(
+            (params.format, params.bytes_per_line, params.lines),
+            (SANE_Frame_SANE_FRAME_GRAY, 4, 2)
+        );
+
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 8];
+        loop {
+            match device.read(&mut buffer) {
+                (GOOD, length) => data.extend_from_slice(&buffer[..length as usize]),
+                (status, length) => {
+                    assert_eq!((status, length), (SANE_Status_SANE_STATUS_EOF, 0));
+                    break;
+                }
+            }
+        }
+        assert_eq!(data, (0..8).collect::<Vec<u8>>());
+
+        let status =
+            unsafe { sane_rusttest_read(device.0, buffer.as_mut_ptr(), 8, ptr::null_mut()) };
+        assert_eq!(status, INVAL);
+        let mut length = -1;
+        let status = unsafe { sane_rusttest_read(device.0, buffer.as_mut_ptr(), -1, &mut length) };
+        assert_eq!((status, length), (INVAL, 0));
+    }
+
+    #[test]
+    fn cancels_a_blocking_read() {
+        let device = Opened::new(b"stub:blocking\0");
+        assert_eq!(device.start(), GOOD);
+
+        let handle = device.0 as usize;
+        let (done, finished) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            let mut buffer = [0u8; 8];
+            let _ = done.send(read(handle as SANE_Handle, &mut buffer));
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !READING.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "read never started");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        unsafe { sane_rusttest_cancel(device.0) };
+        let result = finished
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the cancel didn't interrupt the read");
+        assert_eq!(result, (SANE_Status_SANE_STATUS_CANCELLED, 0));
+        reader.join().unwrap();
+        assert_eq!(CANCELS.load(Ordering::SeqCst), 1);
+
+        // The scan stays cancelled until the next start
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            device.read(&mut buffer),
+            (SANE_Status_SANE_STATUS_CANCELLED, 0)
+        );
+        assert_eq!(CANCELS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn reports_unsupported_io_modes() {
+        let device = Opened::new(b"\0");
+        let unsupported = SANE_Status_SANE_STATUS_UNSUPPORTED;
+        assert_eq!(
+            unsafe { sane_rusttest_set_io_mode(device.0, SANE_TRUE as SANE_Bool) },
+            unsupported
+        );
+        assert_eq!(
+            unsafe { sane_rusttest_set_io_mode(device.0, SANE_FALSE as SANE_Bool) },
+            GOOD
+        );
+        assert_eq!(unsafe { sane_rusttest_set_io_mode(device.0, 2) }, INVAL);
+
+        let mut fd = -1;
+        assert_eq!(
+            unsafe { sane_rusttest_get_select_fd(device.0, &mut fd) },
+            unsupported
+        );
+        assert_eq!(fd, -1);
+        assert_eq!(
+            unsafe { sane_rusttest_get_select_fd(device.0, ptr::null_mut()) },
+            INVAL
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use libsane::{
+    net::{Server, ServerConfig, DEFAULT_PORT},
+    LibSane,
+};
+use log::{info, warn, Level, LevelFilter, Log, Metadata, Record};
+use std::{error::Error, net::TcpListener, path::PathBuf, sync::Arc};
+
+const DEFAULT_CONFIG: &str = "/etc/sane.d/saned.conf";
+
+const USAGE: &str = "Usage: saned [--config <saned.conf>] [--listen <address:port>] [--debug]";
+
+/// Writes log messages to stderr
+struct StderrLogger {
+    level: LevelFilter,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("saned [{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut config_path = None;
+    let mut address = format!("0.0.0.0:{}", DEFAULT_PORT);
+    let mut level = Level::Info;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => config_path = Some(PathBuf::from(args.next().ok_or(USAGE)?)),
+            "--listen" | "-l" => address = args.next().ok_or(USAGE)?,
+            "--debug" | "-d" => level = Level::Debug,
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    log::set_boxed_logger(Box::new(StderrLogger {
+        level: level.to_level_filter(),
+    }))?;
+    log::set_max_level(level.to_level_filter());
+
+    // Without a configuration file, only the local host may connect
+    let config = match config_path {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::load(DEFAULT_CONFIG).unwrap_or_else(|e| {
+            warn!("Could not read {}: {}", DEFAULT_CONFIG, e);
+            ServerConfig::new()
+        }),
+    };
+
+    let sane = Arc::new(LibSane::init(None)?);
+    info!("SANE {} initialized", sane.version());
+
+    let listener = TcpListener::bind(&address)?;
+    info!("Listening on {}", listener.local_addr()?);
+    Server::new(sane, config).serve(listener)?;
+    Ok(())
+}
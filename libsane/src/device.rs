@@ -1,7 +1,11 @@
 use crate::error::{Result, SaneError};
+use crate::fixed::SaneFixed;
+use crate::image::Image;
 use crate::option_descriptor::{OptionDescriptor, OptionDescriptorIterator, Settable, ValueType};
+use crate::scan::Scan;
 use libsane_sys::*;
 use std::{
+    convert::{TryFrom, TryInto},
     ffi::{c_void, CStr},
     marker::PhantomData,
 };
@@ -11,26 +15,112 @@ pub struct Device<'sane> {
     _phantomdata: PhantomData<&'sane ()>,
 }
 
+// SANE handles aren't tied to the thread that opened them. Device stays !Sync, as backends don't
+// have to support concurrent calls on one handle.
+unsafe impl Send for Device<'_> {}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Bool(Box<[bool]>),
     Int(Box<[i32]>),
-    Fixed(Box<[i32]>),
+    Fixed(Box<[SaneFixed]>),
     String(Box<CStr>),
 }
 
 impl Value {
-    pub(crate) unsafe fn as_ptr(&self) -> *mut c_void {
-        match self {
-            Value::Bool(b) => b.as_ptr() as *mut c_void,
-            Value::Int(i) => i.as_ptr() as *mut c_void,
-            Value::Fixed(i) => i.as_ptr() as *mut c_void,
-            Value::String(s) => s.as_ptr() as *mut c_void,
+    /// Encode the value the way `sane_control_option` expects it, padded to at least `size` bytes
+    pub(crate) fn to_buffer(&self, size: usize) -> Vec<u8> {
+        let mut buffer: Vec<u8> = match self {
+            Value::Bool(b) => b
+                .iter()
+                .flat_map(|&b| (if b { SANE_TRUE } else { SANE_FALSE } as SANE_Bool).to_ne_bytes())
+                .collect(),
+            Value::Int(i) => i.iter().flat_map(|i| i.to_ne_bytes()).collect(),
+            Value::Fixed(f) => f.iter().flat_map(|f| f.0.to_ne_bytes()).collect(),
+            Value::String(s) => s.to_bytes_with_nul().to_vec(),
+        };
+        if buffer.len() < size {
+            buffer.resize(size, 0);
         }
+        buffer
+    }
+
+    /// Decode a value of type `value_type` written by `sane_control_option`
+    pub(crate) fn from_buffer(value_type: ValueType, buffer: &[u8]) -> Result<Self> {
+        let words = || {
+            buffer
+                .chunks_exact(std::mem::size_of::<SANE_Word>())
+                .map(|w| SANE_Word::from_ne_bytes([w[0], w[1], w[2], w[3]]))
+        };
+
+        Ok(match value_type {
+            ValueType::Bool => Value::Bool(words().map(bool_from_word).collect::<Result<_>>()?),
+            ValueType::Int => Value::Int(words().collect()),
+            ValueType::Fixed => Value::Fixed(words().map(SaneFixed).collect()),
+            ValueType::String => Value::String(Box::from(
+                CStr::from_bytes_until_nul(buffer).map_err(|_| SaneError::Invalid)?,
+            )),
+            ValueType::Button | ValueType::Group => return Err(SaneError::Invalid),
+        })
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Convert a `SANE_Bool`, rejecting anything but `SANE_TRUE` and `SANE_FALSE`
+pub(crate) fn bool_from_word(word: SANE_Bool) -> Result<bool> {
+    match word as u32 {
+        SANE_FALSE => Ok(false),
+        SANE_TRUE => Ok(true),
+        _ => Err(SaneError::InvalidBool(word)),
+    }
+}
+
+/// Flags reported by the backend after changing an option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptionInfo {
+    /// The backend could not set the exact value and stored an approximation instead.
+    pub inexact: bool,
+    /// Other options may have changed; descriptors and values should be reloaded.
+    pub reload_options: bool,
+    /// The scan parameters may have changed.
+    pub reload_params: bool,
+}
+
+impl From<SANE_Int> for OptionInfo {
+    fn from(info: SANE_Int) -> Self {
+        let info = info as u32;
+        Self {
+            inexact: info & SANE_INFO_INEXACT != 0,
+            reload_options: info & SANE_INFO_RELOAD_OPTIONS != 0,
+            reload_params: info & SANE_INFO_RELOAD_PARAMS != 0,
+        }
+    }
+}
+
+impl From<OptionInfo> for SANE_Int {
+    fn from(info: OptionInfo) -> Self {
+        let mut flags = 0;
+        if info.inexact {
+            flags |= SANE_INFO_INEXACT;
+        }
+        if info.reload_options {
+            flags |= SANE_INFO_RELOAD_OPTIONS;
+        }
+        if info.reload_params {
+            flags |= SANE_INFO_RELOAD_PARAMS;
+        }
+        flags as SANE_Int
+    }
+}
+
+/// Outcome of `Device::set_option`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetOptionResult {
+    /// The value the backend actually stored.
+    pub value: Value,
+    pub info: OptionInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameType {
     ///Band covering human visual range.
     Gray,
@@ -42,42 +132,111 @@ pub enum FrameType {
     Green,
     /// Blue band of a red/green/blue image.
     Blue,
+    /// Backend specific textual data.
+    Text,
+    /// Complete baseline JPEG file.
+    JPEG,
+    /// CCITT Group 3 1-D compressed data (MH).
+    G31D,
+    /// CCITT Group 3 2-D compressed data (MR).
+    G32D,
+    /// CCITT Group 4 2-D compressed data (MMR).
+    G42D,
+    /// Bare infrared band.
+    IR,
+    /// Pixel-interleaved red/green/blue/infrared bands.
+    RGBI,
+    /// Pixel-interleaved gray/infrared bands.
+    GrayI,
+    /// XML data of undefined schema.
+    XML,
+    /// Data in a format described by a MIME type (SANE 2 draft).
+    MIME,
+}
+
+// Frame types that sane.h reserves for later SANE versions, and which bindgen therefore doesn't see
+const SANE_FRAME_MIME: SANE_Frame = 0x06;
+const SANE_FRAME_TEXT: SANE_Frame = 0x0A;
+const SANE_FRAME_JPEG: SANE_Frame = 0x0B;
+const SANE_FRAME_G31D: SANE_Frame = 0x0C;
+const SANE_FRAME_G32D: SANE_Frame = 0x0D;
+const SANE_FRAME_G42D: SANE_Frame = 0x0E;
+const SANE_FRAME_IR: SANE_Frame = 0x0F;
+const SANE_FRAME_RGBI: SANE_Frame = 0x10;
+const SANE_FRAME_GRAYI: SANE_Frame = 0x11;
+const SANE_FRAME_XML: SANE_Frame = 0x12;
+
+impl FrameType {
+    /// Whether the frame holds encoded data rather than raw samples.
+    pub fn is_encoded(&self) -> bool {
+        self.mime_type().is_some() || *self == FrameType::MIME
+    }
+
+    /// MIME type of the frame's data, for frames that don't hold raw samples.
+    ///
+    /// `None` for raw frames, and for `FrameType::MIME` frames since SANE 1
+    /// parameters have no room to carry their type.
+    pub fn mime_type(&self) -> Option<&'static str> {
+        match self {
+            FrameType::Text => Some("text/plain"),
+            FrameType::JPEG => Some("image/jpeg"),
+            FrameType::G31D | FrameType::G32D => Some("image/g3fax"),
+            FrameType::G42D => Some("image/g4fax"),
+            FrameType::XML => Some("application/xml"),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct ScanParameters {
     /// Specifies the format of the next frame to be returned.
-    format: FrameType,
+    pub format: FrameType,
     /// Set to `true` if and only if the frame that is currently being acquired is the last frame of a multi frame image.
-    last_frame: bool,
+    pub last_frame: bool,
     /// How many scan lines the frame is comprised of. None if the number of lines is not known a priori.
-    lines: Option<SANE_Int>,
+    pub lines: Option<SANE_Int>,
     /// Number of bytes per scan line.
-    bytes_per_line: SANE_Int,
+    pub bytes_per_line: SANE_Int,
     /// Number of pixels per scan line.
-    pixels_per_line: SANE_Int,
+    pub pixels_per_line: SANE_Int,
     /// Number of bits per sample.
-    depth: SANE_Int,
+    pub depth: SANE_Int,
+}
+
+impl ScanParameters {
+    /// MIME type of the frame's data, see `FrameType::mime_type`.
+    pub fn mime_type(&self) -> Option<&'static str> {
+        self.format.mime_type()
+    }
 }
 
-impl From<SANE_Parameters> for ScanParameters {
-    fn from(params: SANE_Parameters) -> ScanParameters {
+impl TryFrom<SANE_Parameters> for ScanParameters {
+    type Error = SaneError;
+
+    fn try_from(params: SANE_Parameters) -> Result<ScanParameters> {
         let format = match params.format {
             SANE_Frame_SANE_FRAME_GRAY => FrameType::Gray,
             SANE_Frame_SANE_FRAME_RGB => FrameType::RGB,
             SANE_Frame_SANE_FRAME_RED => FrameType::Red,
             SANE_Frame_SANE_FRAME_GREEN => FrameType::Green,
             SANE_Frame_SANE_FRAME_BLUE => FrameType::Blue,
-            _ => panic!("Unrecognized frame type"),
+            SANE_FRAME_MIME => FrameType::MIME,
+            SANE_FRAME_TEXT => FrameType::Text,
+            SANE_FRAME_JPEG => FrameType::JPEG,
+            SANE_FRAME_G31D => FrameType::G31D,
+            SANE_FRAME_G32D => FrameType::G32D,
+            SANE_FRAME_G42D => FrameType::G42D,
+            SANE_FRAME_IR => FrameType::IR,
+            SANE_FRAME_RGBI => FrameType::RGBI,
+            SANE_FRAME_GRAYI => FrameType::GrayI,
+            SANE_FRAME_XML => FrameType::XML,
+            _ => return Err(SaneError::UnknownFrame(params.format as i32)),
         };
 
-        let last_frame = match params.last_frame {
-            0 => false,
-            1 => true,
-            _ => panic!("Invalid boolean"),
-        };
+        let last_frame = bool_from_word(params.last_frame)?;
 
-        ScanParameters {
+        Ok(ScanParameters {
             format,
             last_frame,
             lines: if params.lines != -1 {
@@ -88,6 +247,45 @@ impl From<SANE_Parameters> for ScanParameters {
             bytes_per_line: params.bytes_per_line,
             pixels_per_line: params.pixels_per_line,
             depth: params.depth,
+        })
+    }
+}
+
+impl From<FrameType> for SANE_Frame {
+    fn from(format: FrameType) -> Self {
+        match format {
+            FrameType::Gray => SANE_Frame_SANE_FRAME_GRAY,
+            FrameType::RGB => SANE_Frame_SANE_FRAME_RGB,
+            FrameType::Red => SANE_Frame_SANE_FRAME_RED,
+            FrameType::Green => SANE_Frame_SANE_FRAME_GREEN,
+            FrameType::Blue => SANE_Frame_SANE_FRAME_BLUE,
+            FrameType::MIME => SANE_FRAME_MIME,
+            FrameType::Text => SANE_FRAME_TEXT,
+            FrameType::JPEG => SANE_FRAME_JPEG,
+            FrameType::G31D => SANE_FRAME_G31D,
+            FrameType::G32D => SANE_FRAME_G32D,
+            FrameType::G42D => SANE_FRAME_G42D,
+            FrameType::IR => SANE_FRAME_IR,
+            FrameType::RGBI => SANE_FRAME_RGBI,
+            FrameType::GrayI => SANE_FRAME_GRAYI,
+            FrameType::XML => SANE_FRAME_XML,
+        }
+    }
+}
+
+impl From<ScanParameters> for SANE_Parameters {
+    fn from(params: ScanParameters) -> Self {
+        SANE_Parameters {
+            format: params.format.into(),
+            last_frame: if params.last_frame {
+                SANE_TRUE
+            } else {
+                SANE_FALSE
+            } as SANE_Bool,
+            bytes_per_line: params.bytes_per_line,
+            pixels_per_line: params.pixels_per_line,
+            lines: params.lines.unwrap_or(-1),
+            depth: params.depth,
         }
     }
 }
@@ -96,6 +294,7 @@ impl<'sane> Device<'sane> {
     pub(crate) fn open_device(name: &CStr) -> Result<Self> {
         let mut handle: SANE_Handle = std::ptr::null_mut();
 
+        let _lock = crate::global_lock();
         unsafe {
             SaneError::from_retcode(sane_open(name.as_ptr(), &mut handle as *mut SANE_Handle))?
         };
@@ -110,34 +309,104 @@ impl<'sane> Device<'sane> {
         self.handle
     }
 
-    pub fn options<'device>(&'device self) -> OptionDescriptorIterator<'device, 'sane> {
-        OptionDescriptorIterator::new(self)
+    /// Number of options, including option 0 which holds this number
+    pub(crate) fn option_count(&self) -> Result<SANE_Int> {
+        let mut count: SANE_Int = 0;
+        self.control_option(
+            0,
+            SANE_Action_SANE_ACTION_GET_VALUE,
+            &mut count as *mut SANE_Int as *mut c_void,
+        )?;
+        Ok(count)
     }
 
-    pub fn set_option(&self, descriptor: &OptionDescriptor, value: &Value) -> Result<()> {
-        match (&value, descriptor.value_type) {
-            (Value::Bool(_), ValueType::Bool) => (),
-            (Value::Int(_), ValueType::Int) => (),
-            (Value::Fixed(_), ValueType::Fixed) => (),
-            (Value::String(_), ValueType::String) => (),
-            _ => panic!("Invalid value type"),
-        }
+    /// The backend's descriptor of option `number`, which stays valid until the device is closed
+    pub(crate) fn raw_descriptor(&self, number: SANE_Int) -> Option<&SANE_Option_Descriptor> {
+        unsafe { sane_get_option_descriptor(self.handle, number).as_ref() }
+    }
 
-        // TODO: Emit warning?
-        if let Settable::Hardware { .. } = descriptor.capabilities.settable {
-            return Ok(());
-        }
+    pub fn options<'device>(&'device self) -> Result<OptionDescriptorIterator<'device, 'sane>> {
+        OptionDescriptorIterator::new(self)
+    }
 
+    pub(crate) fn control_option(
+        &self,
+        option: SANE_Int,
+        action: SANE_Action,
+        value: *mut c_void,
+    ) -> Result<OptionInfo> {
+        let mut info: SANE_Int = 0;
         unsafe {
             SaneError::from_retcode(sane_control_option(
                 self.handle,
-                descriptor.number,
-                SANE_Action_SANE_ACTION_SET_VALUE,
-                value.as_ptr() as *mut c_void,
-                std::ptr::null_mut(),
+                option,
+                action,
+                value,
+                &mut info as *mut SANE_Int,
             ))?
         }
-        Ok(())
+        Ok(info.into())
+    }
+
+    /// Set the value of an option. Returns the value the backend actually stored, which may differ
+    /// from `value` if the backend rounded it, along with the flags it reported.
+    pub fn set_option(
+        &self,
+        descriptor: &OptionDescriptor,
+        value: &Value,
+    ) -> Result<SetOptionResult> {
+        descriptor.validate(value)?;
+
+        // The backend writes the stored value back, so the buffer must have the option's full size
+        let mut buffer = value.to_buffer(descriptor.size as usize);
+        let info = self.control_option(
+            descriptor.number,
+            SANE_Action_SANE_ACTION_SET_VALUE,
+            buffer.as_mut_ptr() as *mut c_void,
+        )?;
+
+        Ok(SetOptionResult {
+            value: Value::from_buffer(descriptor.value_type, &buffer)?,
+            info,
+        })
+    }
+
+    /// Let the backend pick a value for an option automatically, e.g. auto-exposure.
+    /// Fails with `SaneError::Unsupported` if the option lacks the automatic capability.
+    pub fn set_option_auto(&self, descriptor: &OptionDescriptor) -> Result<OptionInfo> {
+        if !descriptor.capabilities.automatic {
+            return Err(SaneError::Unsupported);
+        }
+
+        self.control_option(
+            descriptor.number,
+            SANE_Action_SANE_ACTION_SET_AUTO,
+            std::ptr::null_mut(),
+        )
+    }
+
+    /// Press a button option, triggering its action, e.g. calibration.
+    pub fn press(&self, descriptor: &OptionDescriptor) -> Result<OptionInfo> {
+        match descriptor.value_type {
+            ValueType::Button => (),
+            _ => return Err(SaneError::Invalid),
+        }
+
+        if let Settable::Hardware { .. } = descriptor.capabilities.settable {
+            return Err(SaneError::Unsupported);
+        }
+
+        if descriptor.capabilities.inactive {
+            return Err(SaneError::Invalid);
+        }
+
+        // Buttons have no value, but some backends don't expect a null pointer
+        let mut dummy: SANE_Word = 0;
+        self.control_option(
+            descriptor.number,
+            SANE_Action_SANE_ACTION_SET_VALUE,
+            &mut dummy as *mut SANE_Word as *mut c_void,
+        )
     }
 
     pub fn get_option(&self, descriptor: &OptionDescriptor) -> Result<Option<Value>> {
@@ -155,36 +424,13 @@ impl<'sane> Device<'sane> {
             _ => (),
         }
 
-        // TODO: This sucks fuck
-        unsafe {
-            SaneError::from_retcode(sane_control_option(
-                self.handle,
-                descriptor.number,
-                SANE_Action_SANE_ACTION_GET_VALUE,
-                buffer.as_mut_ptr() as *mut c_void,
-                std::ptr::null_mut(),
-            ))?;
-
-            let buffer = buffer.into_boxed_slice();
-            let len = buffer.len();
-            let ptr = buffer.as_ptr();
-            std::mem::forget(buffer);
+        self.control_option(
+            descriptor.number,
+            SANE_Action_SANE_ACTION_GET_VALUE,
+            buffer.as_mut_ptr() as *mut c_void,
+        )?;
 
-            // TODO: Check size before passing the pointer?
-            return Ok(Some(match descriptor.value_type {
-                ValueType::Bool => {
-                    Value::Bool({ Box::from(std::slice::from_raw_parts(ptr as *const bool, len)) })
-                }
-                ValueType::Int => Value::Int({
-                    Box::from(std::slice::from_raw_parts(ptr as *const i32, len / 4))
-                }),
-                ValueType::Fixed => Value::Fixed({
-                    Box::from(std::slice::from_raw_parts(ptr as *const i32, len / 4))
-                }),
-                ValueType::String => Value::String(Box::from(CStr::from_ptr(ptr as *const i8))),
-                _ => unreachable!(),
-            }));
-        }
+        Value::from_buffer(descriptor.value_type, &buffer).map(Some)
     }
 
     pub fn get_params(&self) -> Result<ScanParameters> {
@@ -202,12 +448,23 @@ impl<'sane> Device<'sane> {
                 &mut parameters as *mut SANE_Parameters,
             ))?;
         }
-        Ok(parameters.into())
+        parameters.try_into()
+    }
+
+    /// Start acquiring an image. The scan is cancelled when the returned session is dropped.
+    pub fn start<'device>(&'device mut self) -> Result<Scan<'device, 'sane>> {
+        Scan::start(self)
+    }
+
+    /// Acquire a whole image, assembling the frames of multi frame scans.
+    pub fn scan_image(&mut self) -> Result<Image> {
+        Image::acquire(self)
     }
 }
 
 impl Drop for Device<'_> {
     fn drop(&mut self) {
+        let _lock = crate::global_lock();
         unsafe {
             sane_close(self.handle);
         }
@@ -1,20 +1,18 @@
-use crate::{
-    device::Device,
-    error::{Result, SaneError},
-    LibSane,
-};
+use crate::error::{Result, SaneError};
 use libsane_sys::*;
 use std::{ffi::CStr, marker::PhantomData};
 
+/// A device description borrowed from the library's device list, which is only valid until the
+/// list is fetched again.
 #[derive(Debug)]
-pub struct DeviceDescription<'sane> {
-    pub name: &'sane CStr,
-    pub vendor: &'sane CStr,
-    pub model: &'sane CStr,
-    pub type_: &'sane CStr,
+pub(crate) struct DeviceDescription<'sane> {
+    name: &'sane CStr,
+    vendor: &'sane CStr,
+    model: &'sane CStr,
+    type_: &'sane CStr,
 }
 
-impl DeviceDescription<'_> {
+impl<'sane> DeviceDescription<'sane> {
     fn from_ptr(ptr: *const SANE_Device) -> Self {
         unsafe {
             Self {
@@ -25,20 +23,45 @@ impl DeviceDescription<'_> {
             }
         }
     }
+}
+
+/// An owned device description, which stays valid after the device list is fetched again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub vendor: String,
+    pub model: String,
+    pub type_: String,
+}
+
+impl From<&DeviceDescription<'_>> for DeviceInfo {
+    fn from(description: &DeviceDescription) -> Self {
+        Self {
+            name: description.name.to_string_lossy().into_owned(),
+            vendor: description.vendor.to_string_lossy().into_owned(),
+            model: description.model.to_string_lossy().into_owned(),
+            type_: description.type_.to_string_lossy().into_owned(),
+        }
+    }
+}
 
-    pub fn open(&self, _: &LibSane) -> Result<Device> {
-        Device::open_device(self.name)
+impl From<DeviceDescription<'_>> for DeviceInfo {
+    fn from(description: DeviceDescription) -> Self {
+        Self::from(&description)
     }
 }
 
-pub struct DeviceListIter<'sane> {
+/// Iterates over the library's device list, see `DeviceDescription`
+pub(crate) struct DeviceListIter<'sane> {
     devices: *mut *const SANE_Device,
     position: isize,
     _phantomdata: PhantomData<&'sane ()>,
 }
 
 impl<'sane> DeviceListIter<'sane> {
-    pub(crate) fn new(_libsane: &'sane LibSane, local_only: bool) -> Result<Self> {
+    /// Fetch the device list. The caller must hold the global lock and make sure the list isn't
+    /// fetched again during `'sane`.
+    pub(crate) fn new(local_only: bool) -> Result<Self> {
         let local_only = if local_only { 1 } else { 0 };
         let mut devices: *mut *const SANE_Device = std::ptr::null_mut();
         unsafe {
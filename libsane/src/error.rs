@@ -1,5 +1,5 @@
 use libsane_sys::*;
-use std::fmt;
+use std::{fmt, io};
 
 pub type Result<T> = std::result::Result<T, SaneError>;
 
@@ -17,6 +17,36 @@ pub enum SaneError {
     Io,
     Memory,
     AccessDenied,
+    WarmingUp,
+    HardwareLocked,
+    /// A `LibSane` instance already exists in this process.
+    AlreadyInitialized,
+    /// The SANE library could not be loaded at runtime (`dlopen` feature).
+    LibraryNotFound,
+    /// The device does not expose the named option.
+    MissingOption(&'static str),
+    /// A `SANE_Bool` was neither `SANE_TRUE` nor `SANE_FALSE`.
+    InvalidBool(SANE_Bool),
+    /// A value does not match the type of the option it was meant for.
+    TypeMismatch,
+    /// A value lies outside of the option's constraint, or does not fit into the option.
+    ConstraintViolation,
+    /// The library returned a status code this crate does not know.
+    UnknownStatus(i32),
+    /// The backend reported a frame type this crate does not know.
+    UnknownFrame(i32),
+    /// The backend reported a unit this crate does not know.
+    UnknownUnit(i32),
+    /// The backend reported an option value type this crate does not know.
+    UnknownValueType(i32),
+    /// The backend reported a constraint type this crate does not know.
+    UnknownConstraint(i32),
+    /// The backend reported a constraint with a null pointer or a negative length.
+    MalformedConstraint,
+    /// A connection to a SANE network peer failed.
+    Network(io::ErrorKind),
+    /// A SANE network peer closed the connection in the middle of a message.
+    Protocol,
 }
 
 impl fmt::Display for SaneError {
@@ -36,11 +66,102 @@ impl fmt::Display for SaneError {
                 SaneError::Io => "Error during device I/O.",
                 SaneError::Memory => "Out of memory.",
                 SaneError::AccessDenied => "Access to resource has been denied.  ,",
+                SaneError::WarmingUp => "Lamp not ready, please retry.",
+                SaneError::HardwareLocked => "Scanner mechanism locked for transport.",
+                SaneError::AlreadyInitialized => "SANE library is already initialized.",
+                SaneError::LibraryNotFound => "SANE library could not be loaded.",
+                SaneError::TypeMismatch => "Value does not match the option's type.",
+                SaneError::ConstraintViolation => "Value violates the option's constraint.",
+                SaneError::MalformedConstraint => "Option constraint is malformed.",
+                SaneError::Protocol => "Connection closed in the middle of a message.",
+                SaneError::Network(kind) => {
+                    return write!(f, "Network error: {}.", kind);
+                }
+                SaneError::MissingOption(name) => {
+                    return write!(f, "Device does not expose the \"{}\" option.", name);
+                }
+                SaneError::InvalidBool(word) => {
+                    return write!(f, "Invalid boolean value {}.", word);
+                }
+                SaneError::UnknownStatus(code) => {
+                    return write!(f, "Unrecognized SANE status code {}.", code);
+                }
+                SaneError::UnknownFrame(frame) => {
+                    return write!(f, "Unrecognized frame type {}.", frame);
+                }
+                SaneError::UnknownUnit(unit) => {
+                    return write!(f, "Unrecognized unit {}.", unit);
+                }
+                SaneError::UnknownValueType(value_type) => {
+                    return write!(f, "Unrecognized value type {}.", value_type);
+                }
+                SaneError::UnknownConstraint(constraint) => {
+                    return write!(f, "Unrecognized constraint type {}.", constraint);
+                }
             }
         )
     }
 }
 
+impl std::error::Error for SaneError {}
+
+impl From<SaneError> for io::Error {
+    /// Wrap a `SaneError`, which stays reachable through `io::Error::get_ref`.
+    fn from(error: SaneError) -> Self {
+        let kind = match error {
+            SaneError::Unsupported => io::ErrorKind::Unsupported,
+            SaneError::DeviceBusy => io::ErrorKind::ResourceBusy,
+            SaneError::Invalid => io::ErrorKind::InvalidInput,
+            SaneError::EOF => io::ErrorKind::UnexpectedEof,
+            SaneError::Memory => io::ErrorKind::OutOfMemory,
+            SaneError::AccessDenied => io::ErrorKind::PermissionDenied,
+            SaneError::WarmingUp => io::ErrorKind::ResourceBusy,
+            SaneError::AlreadyInitialized => io::ErrorKind::AlreadyExists,
+            SaneError::LibraryNotFound => io::ErrorKind::NotFound,
+            SaneError::MissingOption(_) => io::ErrorKind::NotFound,
+            SaneError::InvalidBool(_) => io::ErrorKind::InvalidData,
+            SaneError::TypeMismatch | SaneError::ConstraintViolation => io::ErrorKind::InvalidInput,
+            SaneError::UnknownStatus(_) => io::ErrorKind::Other,
+            SaneError::UnknownFrame(_)
+            | SaneError::UnknownUnit(_)
+            | SaneError::UnknownValueType(_)
+            | SaneError::UnknownConstraint(_)
+            | SaneError::MalformedConstraint => io::ErrorKind::InvalidData,
+            SaneError::Network(kind) => kind,
+            SaneError::Protocol => io::ErrorKind::UnexpectedEof,
+            SaneError::Cancelled
+            | SaneError::Jammed
+            | SaneError::NoDocs
+            | SaneError::CoverOpen
+            | SaneError::Io
+            | SaneError::HardwareLocked => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+impl From<io::Error> for SaneError {
+    /// Recover a wrapped `SaneError`. Other I/O errors come from network connections, where an
+    /// unexpected end of file means the peer hung up mid-message.
+    fn from(error: io::Error) -> Self {
+        if let Some(error) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<SaneError>())
+        {
+            return *error;
+        }
+
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => SaneError::Protocol,
+            kind => SaneError::Network(kind),
+        }
+    }
+}
+
+// Status codes that sane.h reserves for later SANE versions, and which bindgen therefore doesn't see
+const SANE_STATUS_WARMING_UP: SANE_Status = 12;
+const SANE_STATUS_HW_LOCKED: SANE_Status = 13;
+
 impl SaneError {
     pub fn from_retcode(code: SANE_Status) -> std::result::Result<(), Self> {
         match code {
@@ -56,7 +177,43 @@ impl SaneError {
             SANE_Status_SANE_STATUS_IO_ERROR => Err(SaneError::Io),
             SANE_Status_SANE_STATUS_NO_MEM => Err(SaneError::Memory),
             SANE_Status_SANE_STATUS_ACCESS_DENIED => Err(SaneError::AccessDenied),
-            _ => panic!("Unrecognized or unsupported SANE return code."),
+            SANE_STATUS_WARMING_UP => Err(SaneError::WarmingUp),
+            SANE_STATUS_HW_LOCKED => Err(SaneError::HardwareLocked),
+            _ => Err(SaneError::UnknownStatus(code as i32)),
+        }
+    }
+
+    /// The status code reporting this error. Errors that don't come from the library are
+    /// reported as `SANE_STATUS_INVAL`.
+    pub fn to_retcode(&self) -> SANE_Status {
+        match self {
+            SaneError::Unsupported => SANE_Status_SANE_STATUS_UNSUPPORTED,
+            SaneError::Cancelled => SANE_Status_SANE_STATUS_CANCELLED,
+            SaneError::DeviceBusy => SANE_Status_SANE_STATUS_DEVICE_BUSY,
+            SaneError::EOF => SANE_Status_SANE_STATUS_EOF,
+            SaneError::Jammed => SANE_Status_SANE_STATUS_JAMMED,
+            SaneError::NoDocs => SANE_Status_SANE_STATUS_NO_DOCS,
+            SaneError::CoverOpen => SANE_Status_SANE_STATUS_COVER_OPEN,
+            SaneError::Io | SaneError::Network(_) | SaneError::Protocol => {
+                SANE_Status_SANE_STATUS_IO_ERROR
+            }
+            SaneError::Memory => SANE_Status_SANE_STATUS_NO_MEM,
+            SaneError::AccessDenied => SANE_Status_SANE_STATUS_ACCESS_DENIED,
+            SaneError::WarmingUp => SANE_STATUS_WARMING_UP,
+            SaneError::HardwareLocked => SANE_STATUS_HW_LOCKED,
+            SaneError::LibraryNotFound => SANE_Status_SANE_STATUS_UNSUPPORTED,
+            SaneError::UnknownStatus(code) => *code as SANE_Status,
+            SaneError::Invalid
+            | SaneError::AlreadyInitialized
+            | SaneError::MissingOption(_)
+            | SaneError::InvalidBool(_)
+            | SaneError::TypeMismatch
+            | SaneError::ConstraintViolation
+            | SaneError::UnknownFrame(_)
+            | SaneError::UnknownUnit(_)
+            | SaneError::UnknownValueType(_)
+            | SaneError::UnknownConstraint(_)
+            | SaneError::MalformedConstraint => SANE_Status_SANE_STATUS_INVAL,
         }
     }
 }
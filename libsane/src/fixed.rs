@@ -0,0 +1,99 @@
+use libsane_sys::*;
+use std::fmt;
+
+/// A SANE fixed-point number, with 16 integer and 16 fractional bits.
+///
+/// Every `SaneFixed` converts to `f64` exactly, and back again to the same value.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SaneFixed(pub SANE_Fixed);
+
+impl SaneFixed {
+    /// Value of one unit in the raw representation.
+    pub const SCALE: f64 = (1 << SANE_FIXED_SCALE_SHIFT) as f64;
+
+    /// Convert from a floating point number, like `SANE_FIX`.
+    /// Values in between representable numbers are rounded to the nearest one.
+    pub fn from_f64(value: f64) -> Self {
+        SaneFixed((value * Self::SCALE).round() as SANE_Fixed)
+    }
+
+    /// Convert to a floating point number, like `SANE_UNFIX`.
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Self::SCALE
+    }
+}
+
+impl From<f64> for SaneFixed {
+    fn from(value: f64) -> Self {
+        SaneFixed::from_f64(value)
+    }
+}
+
+impl From<SaneFixed> for f64 {
+    fn from(value: SaneFixed) -> Self {
+        value.to_f64()
+    }
+}
+
+impl fmt::Display for SaneFixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest step between two fixed-point numbers
+    const STEP: f64 = 1.0 / SaneFixed::SCALE;
+
+    #[test]
+    fn round_trips_through_f64() {
+        for &raw in &[0, 1, -1, 0x10000, 0x7fff_ffff, -0x8000_0000, 0x0123_4567] {
+            let fixed = SaneFixed(raw);
+            assert_eq!(SaneFixed::from_f64(fixed.to_f64()), fixed);
+            assert_eq!(SaneFixed::from(f64::from(fixed)), fixed);
+        }
+    }
+
+    #[test]
+    fn converts_whole_and_fractional_numbers() {
+        assert_eq!(SaneFixed::from_f64(1.0), SaneFixed(0x10000));
+        assert_eq!(
+            SaneFixed::from_f64(215.9).to_f64(),
+            14_149_222.0 / SaneFixed::SCALE
+        );
+        assert_eq!(SaneFixed::from_f64(0.25).0, 0x4000);
+        assert_eq!(SaneFixed(0x18000).to_f64(), 1.5);
+    }
+
+    #[test]
+    fn converts_negative_numbers() {
+        assert_eq!(SaneFixed::from_f64(-1.0), SaneFixed(-0x10000));
+        assert_eq!(SaneFixed::from_f64(-0.25).0, -0x4000);
+        assert_eq!(SaneFixed(-0x18000).to_f64(), -1.5);
+        assert_eq!(SaneFixed::from_f64(-32768.0), SaneFixed(i32::MIN));
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_step() {
+        assert_eq!(SaneFixed::from_f64(STEP * 0.49).0, 0);
+        assert_eq!(SaneFixed::from_f64(STEP * 0.5).0, 1);
+        assert_eq!(SaneFixed::from_f64(STEP * 1.49).0, 1);
+        assert_eq!(SaneFixed::from_f64(STEP * 1.51).0, 2);
+        assert_eq!(SaneFixed::from_f64(-STEP * 0.49).0, 0);
+        assert_eq!(SaneFixed::from_f64(-STEP * 0.5).0, -1);
+        assert_eq!(SaneFixed::from_f64(1.0 - STEP * 0.4).0, 0x10000);
+    }
+
+    #[test]
+    fn displays_as_decimal() {
+        assert_eq!(SaneFixed::from_f64(1.5).to_string(), "1.5");
+        assert_eq!(SaneFixed::from_f64(-0.25).to_string(), "-0.25");
+        assert_eq!(SaneFixed::from_f64(300.0).to_string(), "300");
+        assert_eq!(SaneFixed(1).to_string(), "0.0000152587890625");
+        assert_eq!(format!("{:.2}", SaneFixed::from_f64(2.0 / 3.0)), "0.67");
+    }
+}
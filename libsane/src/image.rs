@@ -0,0 +1,300 @@
+use crate::device::{Device, FrameType, ScanParameters};
+use crate::error::{Result, SaneError};
+use crate::scan::Scan;
+use libsane_sys::SANE_Int;
+use std::convert::TryFrom;
+
+/// Size of the buffer handed to `sane_read` while assembling an image
+const CHUNK_SIZE: usize = 32 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// One sample per pixel.
+    Gray,
+    /// Pixel-interleaved red/green/blue samples.
+    RGB,
+    /// One infrared sample per pixel.
+    IR,
+    /// Pixel-interleaved gray/infrared samples.
+    GrayI,
+    /// Pixel-interleaved red/green/blue/infrared samples.
+    RGBI,
+    /// Encoded data, such as a JPEG file, in the format of the given frame type.
+    Encoded(FrameType),
+}
+
+impl ChannelLayout {
+    /// Number of samples per pixel, or 0 for encoded data.
+    pub fn channels(&self) -> usize {
+        match self {
+            ChannelLayout::Gray | ChannelLayout::IR => 1,
+            ChannelLayout::GrayI => 2,
+            ChannelLayout::RGB => 3,
+            ChannelLayout::RGBI => 4,
+            ChannelLayout::Encoded(_) => 0,
+        }
+    }
+}
+
+/// A complete image assembled from all frames of a scan.
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// Number of pixels per line.
+    pub width: usize,
+    /// Number of lines.
+    pub height: usize,
+    /// Number of bits per sample.
+    pub depth: usize,
+    /// Arrangement of samples within a pixel.
+    pub layout: ChannelLayout,
+    /// Number of bytes per line in `data`, which may include padding.
+    pub bytes_per_line: usize,
+    /// Raw sample data, `height` lines of `bytes_per_line` bytes each,
+    /// or the complete data of a `ChannelLayout::Encoded` frame.
+    pub data: Vec<u8>,
+}
+
+/// Convert a size reported by the backend, rejecting negative values
+fn dimension(value: SANE_Int) -> Result<usize> {
+    usize::try_from(value).map_err(|_| SaneError::Invalid)
+}
+
+/// A single frame as delivered by the backend
+struct Frame {
+    params: ScanParameters,
+    /// `params.pixels_per_line`, checked to be non-negative
+    width: usize,
+    /// `params.bytes_per_line`, checked to be non-negative
+    bytes_per_line: usize,
+    /// `params.depth`, checked to be non-negative
+    depth: usize,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn read(scan: &mut Scan) -> Result<Self> {
+        let params = scan.parameters()?;
+        let width = dimension(params.pixels_per_line)?;
+        let bytes_per_line = dimension(params.bytes_per_line)?;
+        let depth = dimension(params.depth)?;
+
+        // Hand-held scanners don't know the number of lines up front, so the buffer grows as data arrives
+        let expected = match params.lines {
+            Some(lines) => dimension(lines)?
+                .checked_mul(bytes_per_line)
+                .ok_or(SaneError::Invalid)?,
+            None => 0,
+        };
+        let mut data = Vec::new();
+        data.try_reserve(expected).map_err(|_| SaneError::Memory)?;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        while let Some(length) = scan.read(&mut chunk)? {
+            data.extend_from_slice(&chunk[..length]);
+        }
+
+        Ok(Self {
+            params,
+            width,
+            bytes_per_line,
+            depth,
+            data,
+        })
+    }
+
+    fn height(&self) -> usize {
+        match self.bytes_per_line {
+            0 => 0,
+            bytes_per_line => self.data.len() / bytes_per_line,
+        }
+    }
+
+    /// Check that a line of `channels` samples per pixel fits into `bytes_per_line`
+    fn check_stride(&self, channels: usize) -> Result<()> {
+        let bits = self
+            .width
+            .checked_mul(channels)
+            .and_then(|samples| samples.checked_mul(self.depth))
+            .ok_or(SaneError::Invalid)?;
+        if bits.div_ceil(8) <= self.bytes_per_line {
+            Ok(())
+        } else {
+            Err(SaneError::Invalid)
+        }
+    }
+}
+
+impl Image {
+    pub(crate) fn acquire(device: &mut Device) -> Result<Self> {
+        let mut scan = device.start()?;
+        let mut single = None;
+        let mut planes: [Option<Frame>; 3] = [None, None, None];
+
+        loop {
+            let frame = Frame::read(&mut scan)?;
+            let last_frame = frame.params.last_frame;
+            match frame.params.format {
+                FrameType::Red => planes[0] = Some(frame),
+                FrameType::Green => planes[1] = Some(frame),
+                FrameType::Blue => planes[2] = Some(frame),
+                _ => single = Some(frame),
+            }
+            if last_frame {
+                break;
+            }
+            scan.next_frame()?;
+        }
+
+        match (single, planes) {
+            (Some(frame), _) => Self::from_frame(frame),
+            (None, [Some(red), Some(green), Some(blue)]) => Self::interleave([red, green, blue]),
+            _ => Err(SaneError::Invalid),
+        }
+    }
+
+    fn from_frame(mut frame: Frame) -> Result<Self> {
+        let layout = match frame.params.format {
+            FrameType::RGB => ChannelLayout::RGB,
+            FrameType::IR => ChannelLayout::IR,
+            FrameType::GrayI => ChannelLayout::GrayI,
+            FrameType::RGBI => ChannelLayout::RGBI,
+            format if format.is_encoded() => ChannelLayout::Encoded(format),
+            _ => ChannelLayout::Gray,
+        };
+
+        // Encoded data isn't organized in lines, so keep all of it
+        if let ChannelLayout::Encoded(_) = layout {
+            return Ok(Self {
+                width: frame.width,
+                height: frame.params.lines.map(dimension).transpose()?.unwrap_or(0),
+                depth: frame.depth,
+                layout,
+                bytes_per_line: 0,
+                data: frame.data,
+            });
+        }
+
+        frame.check_stride(layout.channels())?;
+        let height = frame.height();
+        frame.data.truncate(height * frame.bytes_per_line);
+
+        Ok(Self {
+            width: frame.width,
+            height,
+            depth: frame.depth,
+            layout,
+            bytes_per_line: frame.bytes_per_line,
+            data: frame.data,
+        })
+    }
+
+    /// Merge the separate red, green and blue frames of a three-pass scan into one RGB image
+    fn interleave(planes: [Frame; 3]) -> Result<Self> {
+        let first = &planes[0];
+        let consistent = planes.iter().all(|plane| {
+            plane.width == first.width
+                && plane.bytes_per_line == first.bytes_per_line
+                && plane.depth == first.depth
+                && plane.height() == first.height()
+        });
+        if !consistent {
+            return Err(SaneError::Invalid);
+        }
+
+        let sample_size = match first.depth {
+            8 => 1,
+            16 => 2,
+            _ => return Err(SaneError::Unsupported),
+        };
+        first.check_stride(1)?;
+
+        let width = first.width;
+        let depth = first.depth;
+        let height = first.height();
+        let in_stride = first.bytes_per_line;
+        let out_stride = width
+            .checked_mul(3 * sample_size)
+            .ok_or(SaneError::Invalid)?;
+
+        let mut data = Vec::with_capacity(height * out_stride);
+        for line in 0..height {
+            for pixel in 0..width {
+                for plane in &planes {
+                    let start = line * in_stride + pixel * sample_size;
+                    data.extend_from_slice(&plane.data[start..start + sample_size]);
+                }
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+            layout: ChannelLayout::RGB,
+            bytes_per_line: out_stride,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(format: FrameType, width: usize, bytes_per_line: usize, data: Vec<u8>) -> Frame {
+        Frame {
+            params: ScanParameters {
+                format,
+                last_frame: false,
+                lines: None,
+                bytes_per_line: bytes_per_line as SANE_Int,
+                pixels_per_line: width as SANE_Int,
+                depth: 8,
+            },
+            width,
+            bytes_per_line,
+            depth: 8,
+            data,
+        }
+    }
+
+    #[test]
+    fn interleaves_planes() {
+        let image = Image::interleave([
+            frame(FrameType::Red, 2, 2, vec![1, 2, 3, 4]),
+            frame(FrameType::Green, 2, 2, vec![5, 6, 7, 8]),
+            frame(FrameType::Blue, 2, 2, vec![9, 10, 11, 12]),
+        ])
+        .unwrap();
+        assert_eq!((image.width, image.height, image.bytes_per_line), (2, 2, 6));
+        assert_eq!(image.data, [1, 5, 9, 2, 6, 10, 3, 7, 11, 4, 8, 12]);
+    }
+
+    #[test]
+    fn rejects_planes_of_different_height() {
+        let result = Image::interleave([
+            frame(FrameType::Red, 2, 2, vec![1, 2, 3, 4]),
+            frame(FrameType::Green, 2, 2, vec![5, 6]),
+            frame(FrameType::Blue, 2, 2, vec![9, 10, 11, 12]),
+        ]);
+        assert!(matches!(result, Err(SaneError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_lines_shorter_than_their_pixels() {
+        let plane = || frame(FrameType::Red, 4, 2, vec![0; 8]);
+        assert!(matches!(
+            Image::interleave([plane(), plane(), plane()]),
+            Err(SaneError::Invalid)
+        ));
+        assert!(matches!(
+            Image::from_frame(frame(FrameType::RGB, 4, 6, vec![0; 12])),
+            Err(SaneError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn keeps_whole_lines_of_a_frame() {
+        let image = Image::from_frame(frame(FrameType::Gray, 2, 3, vec![0; 7])).unwrap();
+        assert_eq!((image.height, image.data.len()), (2, 6));
+    }
+}
@@ -1,44 +1,150 @@
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
+#[cfg(feature = "async")]
+mod async_scan;
+mod auth;
+pub mod backend;
 mod device;
 mod device_list;
 mod error;
+mod fixed;
+mod image;
+pub mod net;
 mod option_descriptor;
+mod scan;
+mod shared;
+mod version;
+mod watcher;
+pub mod well_known;
+#[cfg(feature = "async")]
+pub use async_scan::AsyncFrame;
 //pub use device::{Device, Value};
 pub use device::*;
-pub use device_list::{DeviceDescription, DeviceListIter};
+pub use device_list::DeviceInfo;
 pub use error::{Result, SaneError};
+pub use fixed::SaneFixed;
+pub use image::{ChannelLayout, Image};
 pub use option_descriptor::*;
+pub use scan::{Scan, SelectFd};
+pub use shared::SharedDevice;
+pub use version::SaneVersion;
+pub use watcher::{DeviceEvent, DeviceWatcher, WatcherHandle};
+pub use well_known::ScanArea;
 
 use libsane_sys::*;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+
+/// Set while a `LibSane` instance exists, since `sane_exit` tears down the library for the whole process
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Serializes the calls that touch the library's global state, i.e. opening and closing devices and
+/// listing them
+static GLOBAL_LOCK: Mutex<()> = Mutex::new(());
+
+pub(crate) fn global_lock() -> MutexGuard<'static, ()> {
+    GLOBAL_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 /// LibSANE C library representation
-pub struct LibSane;
+///
+/// Only one instance can exist at a time; `init` fails with `SaneError::AlreadyInitialized` while
+/// another one is alive.
+///
+/// `LibSane` is `Send` and `Sync`: SANE has no thread affinity, and the calls that touch global
+/// state are serialized internally. A [`Device`] is `Send` but not `Sync`, because backends don't
+/// have to cope with concurrent calls on the same handle. Use [`SharedDevice`] to share a device
+/// between threads.
+pub struct LibSane {
+    version: SaneVersion,
+}
 
 impl LibSane {
     /// Initialize the LibSANE library
     pub fn init(callback: SANE_Auth_Callback) -> Result<Self> {
+        Self::claim()?;
+        Self::init_claimed(callback).inspect_err(|_| Self::release())
+    }
+
+    fn claim() -> Result<()> {
+        if INITIALIZED.swap(true, Ordering::AcqRel) {
+            return Err(SaneError::AlreadyInitialized);
+        }
+        Ok(())
+    }
+
+    fn release() {
+        INITIALIZED.store(false, Ordering::Release);
+    }
+
+    fn init_claimed(callback: SANE_Auth_Callback) -> Result<Self> {
+        #[cfg(feature = "dlopen")]
+        libsane_sys::load().map_err(|error| {
+            log::debug!("{}", error);
+            SaneError::LibraryNotFound
+        })?;
+
         let mut version: i32 = 0;
         unsafe {
-            SaneError::from_retcode(sane_init(&mut version as *mut i32, callback)).map(|_| LibSane)
+            SaneError::from_retcode(sane_init(&mut version as *mut i32, callback)).map(|_| {
+                LibSane {
+                    version: version.into(),
+                }
+            })
         }
     }
 
-    /// Return an iterator over available device descriptions
-    pub fn list_devices<'a>(&'a self, local_only: bool) -> Result<DeviceListIter<'a>> {
-        DeviceListIter::new(self, local_only)
+    /// Version of the SANE library that was initialized
+    pub fn version(&self) -> SaneVersion {
+        self.version
+    }
+
+    /// Initialize the LibSANE library with a callback supplying credentials for backends that require them.
+    ///
+    /// The callback receives the name of the resource and returns a username and password, or `None`
+    /// to decline. Passwords for resources using the `$MD5$` challenge are hashed automatically.
+    pub fn init_with_auth<F>(callback: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<(String, String)> + Send + Sync + 'static,
+    {
+        // Claim first, so a failed double init doesn't replace the live instance's callback
+        Self::claim()?;
+        auth::register(Box::new(callback));
+        Self::init_claimed(Some(auth::trampoline)).inspect_err(|_| {
+            auth::unregister();
+            Self::release();
+        })
+    }
+
+    /// Return owned descriptions of the available devices
+    pub fn devices(&self, local_only: bool) -> Result<Vec<DeviceInfo>> {
+        // Other threads may fetch the list too, so hold the lock until it is copied
+        let _lock = global_lock();
+        Ok(device_list::DeviceListIter::new(local_only)?
+            .map(DeviceInfo::from)
+            .collect())
     }
 
     /// Open the device with name `name`
     pub fn open_device<'a>(&'a self, name: &str) -> Result<Device<'a>> {
-        let name = std::ffi::CString::new(name).expect("Invalid C String");
+        let name = std::ffi::CString::new(name).map_err(|_| SaneError::Invalid)?;
         Device::open_device(&name)
     }
+
+    /// Open the device with name `name` for use from several threads. The device keeps the library
+    /// alive until it is closed.
+    pub fn open_shared(self: &Arc<Self>, name: &str) -> Result<SharedDevice> {
+        SharedDevice::open(self.clone(), name)
+    }
 }
 
 impl Drop for LibSane {
     fn drop(&mut self) {
         unsafe { sane_exit() }
+        auth::unregister();
+        Self::release();
     }
 }
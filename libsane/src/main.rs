@@ -6,8 +6,12 @@ fn main() -> Result<()> {
     //let options = device.options().collect::<Vec<_>>();
     //let m = std::ffi::CString::new("resolution").unwrap();
     //let res_option = options.iter().find(|&x| x.name == Some(m.as_c_str())).unwrap();
-    for option in device.options() {
+    for option in device.options()? {
+        let option = option?;
         println!("{:?}", option);
+        if !option.capabilities.is_settable() || !option.capabilities.is_active() {
+            continue;
+        }
         let value = device.get_option(&option)?;
         if let Some(v) = &value {
             device.set_option(&option, v)?;
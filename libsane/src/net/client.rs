@@ -0,0 +1,836 @@
+use super::wire::*;
+use crate::{
+    auth::{self, AuthCallback},
+    device::{OptionInfo, ScanParameters, SetOptionResult, Value},
+    device_list::DeviceInfo,
+    error::{Result, SaneError},
+    option_descriptor::{OptionDescriptor, Settable, ValueType},
+    version::SaneVersion,
+};
+use libsane_sys::*;
+use std::{
+    cell::RefCell,
+    convert::{TryFrom, TryInto},
+    ffi::{CStr, CString},
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+};
+
+type Reader = BufReader<TcpStream>;
+type Writer = BufWriter<TcpStream>;
+
+struct Connection {
+    reader: Reader,
+    writer: Writer,
+}
+
+/// A connection to a `saned` server, speaking the SANE network protocol without going through
+/// libsane.
+pub struct NetClient {
+    connection: RefCell<Connection>,
+    /// Where to connect for image data
+    host: IpAddr,
+    version: SaneVersion,
+    auth: Option<Box<AuthCallback>>,
+}
+
+impl NetClient {
+    /// Connect to the server at `address`, e.g. `("scanner-host", net::DEFAULT_PORT)`.
+    /// The server is told the name of the local user from the `USER` environment variable.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<Self> {
+        Self::connect_inner(address, None)
+    }
+
+    /// Connect to the server at `address`, with a callback supplying credentials for resources
+    /// that require them, like `LibSane::init_with_auth`.
+    pub fn connect_with_auth<A, F>(address: A, callback: F) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+        F: Fn(&str) -> Option<(String, String)> + Send + Sync + 'static,
+    {
+        Self::connect_inner(address, Some(Box::new(callback)))
+    }
+
+    fn connect_inner<A: ToSocketAddrs>(
+        address: A,
+        auth: Option<Box<AuthCallback>>,
+    ) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let host = stream.peer_addr()?.ip();
+        let connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        };
+        let mut client = Self {
+            connection: RefCell::new(connection),
+            host,
+            version: PROTOCOL_VERSION,
+            auth,
+        };
+
+        let user = std::env::var("USER")
+            .ok()
+            .and_then(|user| CString::new(user).ok());
+        let version = client.call(
+            |writer| {
+                write_word(writer, SANE_NET_INIT)?;
+                write_word(writer, PROTOCOL_VERSION.into())?;
+                write_string(writer, user.as_deref())
+            },
+            |reader| {
+                let status = read_status(reader)?;
+                let version = read_word(reader)?;
+                Ok((status.map(|_| SaneVersion::from(version)), None))
+            },
+        )?;
+        if version.major != PROTOCOL_VERSION.major {
+            return Err(SaneError::Unsupported);
+        }
+        client.version = version;
+        Ok(client)
+    }
+
+    /// Version reported by the server, whose build number is the protocol version it speaks
+    pub fn version(&self) -> SaneVersion {
+        self.version
+    }
+
+    /// Return descriptions of the devices the server offers
+    pub fn devices(&self) -> Result<Vec<DeviceInfo>> {
+        self.call(
+            |writer| write_word(writer, SANE_NET_GET_DEVICES),
+            |reader| {
+                let status = read_status(reader)?;
+                // The array ends with the null pointer that terminates the C list
+                let mut devices = Vec::new();
+                for _ in 0..read_length(reader)? {
+                    devices.extend(read_device(reader)?);
+                }
+                Ok((status.map(|_| devices), None))
+            },
+        )
+    }
+
+    /// Open the device with name `name`
+    pub fn open(&self, name: &str) -> Result<NetDevice<'_>> {
+        let name = CString::new(name).map_err(|_| SaneError::Invalid)?;
+        let handle = self.call(
+            |writer| {
+                write_word(writer, SANE_NET_OPEN)?;
+                write_string(writer, Some(&name))
+            },
+            |reader| {
+                let status = read_status(reader)?;
+                let handle = read_word(reader)?;
+                Ok((status.map(|_| handle), read_string(reader)?))
+            },
+        )?;
+
+        let mut device = NetDevice {
+            client: self,
+            handle,
+            descriptors: Vec::new(),
+        };
+        device.reload_options()?;
+        Ok(device)
+    }
+
+    /// Send a request and read its reply. Replies naming a resource are followed by an
+    /// authorization, after which the server sends the reply again.
+    fn call<T>(
+        &self,
+        request: impl FnOnce(&mut Writer) -> Result<()>,
+        mut reply: impl FnMut(&mut Reader) -> Result<(Result<T>, Option<CString>)>,
+    ) -> Result<T> {
+        let mut connection = self.connection.borrow_mut();
+        let connection = &mut *connection;
+        request(&mut connection.writer)?;
+        connection.writer.flush()?;
+
+        loop {
+            match reply(&mut connection.reader)? {
+                (_, Some(resource)) => self.authorize(connection, &resource)?,
+                (result, None) => return result,
+            }
+        }
+    }
+
+    /// Call a procedure whose request is a handle and whose reply is a dummy word
+    fn call_with_handle(&self, procedure: SANE_Word, handle: SANE_Word) -> Result<()> {
+        self.call(
+            |writer| {
+                write_word(writer, procedure)?;
+                write_word(writer, handle)
+            },
+            |reader| {
+                read_word(reader)?;
+                Ok((Ok(()), None))
+            },
+        )
+    }
+
+    fn authorize(&self, connection: &mut Connection, resource: &CStr) -> Result<()> {
+        // Without credentials, the server is sent empty ones and denies access
+        let (user, pass) = self
+            .auth
+            .as_ref()
+            .and_then(|callback| auth::credentials(&resource.to_string_lossy(), callback.as_ref()))
+            .unwrap_or_default();
+        let user = CString::new(user).map_err(|_| SaneError::Invalid)?;
+        let pass = CString::new(pass).map_err(|_| SaneError::Invalid)?;
+
+        let writer = &mut connection.writer;
+        write_word(writer, SANE_NET_AUTHORIZE)?;
+        write_string(writer, Some(resource))?;
+        write_string(writer, Some(&user))?;
+        write_string(writer, Some(&pass))?;
+        writer.flush()?;
+        read_word(&mut connection.reader)?;
+        Ok(())
+    }
+}
+
+impl Drop for NetClient {
+    fn drop(&mut self) {
+        let connection = self.connection.get_mut();
+        let _ = write_word(&mut connection.writer, SANE_NET_EXIT);
+        let _ = connection.writer.flush();
+    }
+}
+
+/// A device opened on a `saned` server, see `NetClient::open`.
+pub struct NetDevice<'client> {
+    client: &'client NetClient,
+    handle: SANE_Word,
+    /// Indexed by option number. The server may send null pointers for options without a descriptor.
+    descriptors: Vec<Option<RemoteDescriptor>>,
+}
+
+impl<'client> NetDevice<'client> {
+    /// Descriptors of the device's options, as of the last reload.
+    pub fn options(&self) -> impl Iterator<Item = OptionDescriptor<'_>> {
+        self.descriptors
+            .iter()
+            .enumerate()
+            .filter_map(|(number, descriptor)| {
+                descriptor
+                    .as_ref()
+                    .map(|descriptor| descriptor.descriptor(number as SANE_Int))
+            })
+    }
+
+    /// Fetch the option descriptors again, e.g. after setting an option reported `reload_options`.
+    pub fn reload_options(&mut self) -> Result<()> {
+        let handle = self.handle;
+        self.descriptors = self.client.call(
+            |writer| {
+                write_word(writer, SANE_NET_GET_OPTION_DESCRIPTORS)?;
+                write_word(writer, handle)
+            },
+            |reader| {
+                let mut descriptors = Vec::new();
+                for _ in 0..read_length(reader)? {
+                    descriptors.push(read_descriptor(reader)?);
+                }
+                Ok((Ok(descriptors), None))
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Run `sane_control_option` on the server, returning the info flags and the value it sent back
+    fn control_option(
+        &self,
+        descriptor: &OptionDescriptor,
+        action: SANE_Action,
+        buffer: &[u8],
+    ) -> Result<(OptionInfo, Vec<u8>)> {
+        let handle = self.handle;
+        // Up to protocol version 2, a value was sent even for SANE_ACTION_SET_AUTO
+        let send_value =
+            self.client.version.build < 3 || action != SANE_Action_SANE_ACTION_SET_AUTO;
+        self.client.call(
+            |writer| {
+                write_word(writer, SANE_NET_CONTROL_OPTION)?;
+                write_word(writer, handle)?;
+                write_word(writer, descriptor.number)?;
+                write_word(writer, action as SANE_Word)?;
+                if send_value {
+                    write_word(
+                        writer,
+                        SANE_Value_Type::from(descriptor.value_type) as SANE_Word,
+                    )?;
+                    write_word(writer, descriptor.size)?;
+                    write_value(writer, descriptor.value_type, buffer)?;
+                }
+                Ok(())
+            },
+            |reader| {
+                let status = read_status(reader)?;
+                let info = read_word(reader)?;
+                let value_type = read_value_type(reader)?;
+                let _size = read_word(reader)?;
+                let value = read_value(reader, value_type)?;
+                Ok((status.map(|_| (info.into(), value)), read_string(reader)?))
+            },
+        )
+    }
+
+    /// Set the value of an option, like `Device::set_option`.
+    pub fn set_option(
+        &self,
+        descriptor: &OptionDescriptor,
+        value: &Value,
+    ) -> Result<SetOptionResult> {
+        descriptor.validate(value)?;
+
+        let buffer = value.to_buffer(descriptor.size as usize);
+        let (info, buffer) =
+            self.control_option(descriptor, SANE_Action_SANE_ACTION_SET_VALUE, &buffer)?;
+
+        Ok(SetOptionResult {
+            value: Value::from_buffer(descriptor.value_type, &buffer)?,
+            info,
+        })
+    }
+
+    /// Let the backend pick a value for an option automatically, like `Device::set_option_auto`.
+    pub fn set_option_auto(&self, descriptor: &OptionDescriptor) -> Result<OptionInfo> {
+        if !descriptor.capabilities.automatic {
+            return Err(SaneError::Unsupported);
+        }
+
+        let buffer = vec![0u8; descriptor.size as usize];
+        self.control_option(descriptor, SANE_Action_SANE_ACTION_SET_AUTO, &buffer)
+            .map(|(info, _)| info)
+    }
+
+    pub fn get_option(&self, descriptor: &OptionDescriptor) -> Result<Option<Value>> {
+        if let Settable::Hardware {
+            software_visible: false,
+        } = descriptor.capabilities.settable
+        {
+            return Ok(None);
+        }
+
+        match descriptor.value_type {
+            ValueType::Group | ValueType::Button => return Ok(None),
+            _ => (),
+        }
+
+        let buffer = vec![0u8; descriptor.size as usize];
+        let (_, buffer) =
+            self.control_option(descriptor, SANE_Action_SANE_ACTION_GET_VALUE, &buffer)?;
+
+        Value::from_buffer(descriptor.value_type, &buffer).map(Some)
+    }
+
+    pub fn get_params(&self) -> Result<ScanParameters> {
+        let handle = self.handle;
+        let params = self.client.call(
+            |writer| {
+                write_word(writer, SANE_NET_GET_PARAMETERS)?;
+                write_word(writer, handle)
+            },
+            |reader| {
+                let status = read_status(reader)?;
+                let params = read_parameters(reader)?;
+                Ok((status.map(|_| params), None))
+            },
+        )?;
+        params.try_into()
+    }
+
+    /// Start acquiring an image. The scan is cancelled when the returned session is dropped.
+    pub fn start<'device>(&'device self) -> Result<NetScan<'device, 'client>> {
+        Ok(NetScan {
+            device: self,
+            data: self.start_frame()?,
+        })
+    }
+
+    /// Start a frame and connect to the port the server sends its data to
+    fn start_frame(&self) -> Result<DataStream> {
+        let handle = self.handle;
+        let (port, byte_order) = self.client.call(
+            |writer| {
+                write_word(writer, SANE_NET_START)?;
+                write_word(writer, handle)
+            },
+            |reader| {
+                let status = read_status(reader)?;
+                let port = read_word(reader)?;
+                let byte_order = read_word(reader)?;
+                Ok((status.map(|_| (port, byte_order)), read_string(reader)?))
+            },
+        )?;
+
+        let port = u16::try_from(port).map_err(|_| SaneError::Invalid)?;
+        let stream = TcpStream::connect((self.client.host, port))?;
+
+        // 16 bit samples arrive in the server's byte order
+        let native = if cfg!(target_endian = "little") {
+            LITTLE_ENDIAN
+        } else {
+            BIG_ENDIAN
+        };
+        let swap = byte_order != native && self.get_params()?.depth == 16;
+
+        Ok(DataStream {
+            stream: BufReader::new(stream),
+            record: Vec::new(),
+            position: 0,
+            swap,
+            carry: None,
+            end: None,
+        })
+    }
+}
+
+impl Drop for NetDevice<'_> {
+    fn drop(&mut self) {
+        let _ = self.client.call_with_handle(SANE_NET_CLOSE, self.handle);
+    }
+}
+
+/// Image data arriving on the data port, as records prefixed with their length
+struct DataStream {
+    stream: Reader,
+    record: Vec<u8>,
+    position: usize,
+    /// Whether to swap the bytes of 16 bit samples
+    swap: bool,
+    /// First byte of a sample that was split between two records
+    carry: Option<u8>,
+    /// The status the server sent after the last record
+    end: Option<Result<()>>,
+}
+
+impl DataStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        while self.position == self.record.len() {
+            match self.end {
+                Some(Ok(())) | Some(Err(SaneError::EOF)) => return Ok(None),
+                Some(Err(e)) => return Err(e),
+                None => self.next_record()?,
+            }
+        }
+
+        let length = buf.len().min(self.record.len() - self.position);
+        buf[..length].copy_from_slice(&self.record[self.position..self.position + length]);
+        self.position += length;
+        Ok(Some(length))
+    }
+
+    fn next_record(&mut self) -> Result<()> {
+        let length = read_word(&mut self.stream)? as u32;
+        if length == END_OF_DATA {
+            let mut status = [0u8];
+            self.stream.read_exact(&mut status)?;
+            self.end = Some(SaneError::from_retcode(status[0] as SANE_Status));
+            return Ok(());
+        }
+
+        self.record.clear();
+        self.position = 0;
+        self.record.extend(self.carry.take());
+        let expected = self.record.len() + length as usize;
+        (&mut self.stream)
+            .take(length as u64)
+            .read_to_end(&mut self.record)?;
+        if self.record.len() != expected {
+            return Err(SaneError::Protocol);
+        }
+
+        if self.swap {
+            if self.record.len() % 2 == 1 {
+                self.carry = self.record.pop();
+            }
+            for sample in self.record.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A running acquisition on a `NetDevice`, started by `NetDevice::start`.
+///
+/// The scan is cancelled when this session is dropped.
+pub struct NetScan<'device, 'client> {
+    device: &'device NetDevice<'client>,
+    data: DataStream,
+}
+
+impl NetScan<'_, '_> {
+    /// Parameters of the frame currently being acquired.
+    pub fn parameters(&self) -> Result<ScanParameters> {
+        self.device.get_params()
+    }
+
+    /// Read image data of the current frame into `buf`.
+    /// Returns the number of bytes read, or `None` once the frame is complete.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        self.data.read(buf)
+    }
+
+    /// Start acquiring the next frame of a multi frame image, once the
+    /// current frame is complete and it was not the last one.
+    pub fn next_frame(&mut self) -> Result<()> {
+        self.data = self.device.start_frame()?;
+        Ok(())
+    }
+
+    /// Cancel the scan. Equivalent to dropping the session.
+    pub fn cancel(self) {}
+}
+
+impl Drop for NetScan<'_, '_> {
+    fn drop(&mut self) {
+        let _ = self
+            .device
+            .client
+            .call_with_handle(SANE_NET_CANCEL, self.device.handle);
+    }
+}
+
+impl io::Read for NetScan<'_, '_> {
+    /// Read image data of the current frame. The end of the frame is reported as a zero-length read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(NetScan::read(self, buf)?.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{Shutdown, SocketAddr, TcpListener},
+        thread::{self, JoinHandle},
+    };
+
+    const GOOD: SANE_Word = SANE_Status_SANE_STATUS_GOOD as SANE_Word;
+    const HANDLE: SANE_Word = 7;
+    const RESOLUTION: SANE_Int = 1;
+
+    fn encode(write: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn cstr(bytes: &[u8]) -> &CStr {
+        CStr::from_bytes_with_nul(bytes).unwrap()
+    }
+
+    /// The server end of a recorded session
+    struct Peer {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Peer {
+        /// Read the next request, which must match `request` byte for byte
+        fn expect(&mut self, request: impl FnOnce(&mut Vec<u8>) -> Result<()>) {
+            let expected = encode(request);
+            let mut actual = vec![0u8; expected.len()];
+            self.reader.read_exact(&mut actual).unwrap();
+            assert_eq!(actual, expected);
+        }
+
+        fn reply(&mut self, reply: impl FnOnce(&mut Vec<u8>) -> Result<()>) {
+            self.writer.write_all(&encode(reply)).unwrap();
+        }
+
+        /// Answer `SANE_NET_OPEN` and the descriptor request that follows it
+        fn open(&mut self) {
+            self.expect(|w| {
+                write_word(w, SANE_NET_OPEN)?;
+                write_string(w, Some(cstr(b"stub:0\0")))
+            });
+            self.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, HANDLE)?;
+                write_string(w, None)
+            });
+
+            self.expect(|w| {
+                write_word(w, SANE_NET_GET_OPTION_DESCRIPTORS)?;
+                write_word(w, HANDLE)
+            });
+            let count = SANE_Option_Descriptor {
+                name: std::ptr::null(),
+                title: b"Number of options\0".as_ptr() as SANE_String_Const,
+                desc: std::ptr::null(),
+                type_: SANE_Value_Type_SANE_TYPE_INT,
+                unit: SANE_Unit_SANE_UNIT_NONE,
+                size: 4,
+                cap: SANE_CAP_SOFT_DETECT as SANE_Int,
+                constraint_type: SANE_Constraint_Type_SANE_CONSTRAINT_NONE,
+                constraint: SANE_Option_Descriptor__bindgen_ty_1 {
+                    range: std::ptr::null(),
+                },
+            };
+            let resolution = SANE_Option_Descriptor {
+                name: b"resolution\0".as_ptr() as SANE_String_Const,
+                type_: SANE_Value_Type_SANE_TYPE_INT,
+                unit: SANE_Unit_SANE_UNIT_DPI,
+                cap: (SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT | SANE_CAP_AUTOMATIC) as SANE_Int,
+                ..count
+            };
+            self.reply(|w| unsafe {
+                write_word(w, 2)?;
+                write_descriptor(w, &count)?;
+                write_descriptor(w, &resolution)
+            });
+        }
+
+        fn close(&mut self) {
+            self.expect(|w| {
+                write_word(w, SANE_NET_CLOSE)?;
+                write_word(w, HANDLE)
+            });
+            self.reply(|w| write_word(w, 0));
+        }
+    }
+
+    /// Accept one client, claim to speak protocol version `build` and run `script` against it.
+    /// The client must exit at the end.
+    fn replay(
+        build: u16,
+        script: impl FnOnce(&mut Peer) + Send + 'static,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut peer = Peer {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+
+            // The user name comes from the environment, so only the procedure is checked
+            assert_eq!(read_word(&mut peer.reader).unwrap(), SANE_NET_INIT);
+            read_word(&mut peer.reader).unwrap();
+            read_string(&mut peer.reader).unwrap();
+            let version = SaneVersion {
+                major: 1,
+                minor: 0,
+                build,
+            };
+            peer.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, version.into())
+            });
+
+            script(&mut peer);
+            peer.expect(|w| write_word(w, SANE_NET_EXIT));
+        });
+        (address, server)
+    }
+
+    fn resolution<'a>(device: &'a NetDevice) -> OptionDescriptor<'a> {
+        device
+            .options()
+            .find(|option| option.number == RESOLUTION)
+            .unwrap()
+    }
+
+    #[test]
+    fn lists_and_opens_devices() {
+        let (address, server) = replay(3, |peer| {
+            peer.expect(|w| write_word(w, SANE_NET_GET_DEVICES));
+            peer.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, 2)?;
+                write_device(
+                    w,
+                    Some(&DeviceInfo {
+                        name: "stub:0".to_string(),
+                        vendor: "Stub".to_string(),
+                        model: "Flatbed".to_string(),
+                        type_: "flatbed scanner".to_string(),
+                    }),
+                )?;
+                write_device(w, None)
+            });
+            peer.open();
+            peer.close();
+        });
+
+        let client = NetClient::connect(address).unwrap();
+        assert_eq!(client.version().build, 3);
+        let devices = client.devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].model, "Flatbed");
+
+        let device = client.open("stub:0").unwrap();
+        assert_eq!(device.options().count(), 2);
+        assert_eq!(resolution(&device).name, Some(cstr(b"resolution\0")));
+        drop(device);
+        drop(client);
+        server.join().unwrap();
+    }
+
+    fn reply_to_auto(peer: &mut Peer) {
+        peer.reply(|w| {
+            write_word(w, GOOD)?;
+            write_word(w, SANE_INFO_RELOAD_PARAMS as SANE_Word)?;
+            write_word(w, 0)?;
+            write_word(w, 0)?;
+            write_word(w, 0)?;
+            write_string(w, None)
+        });
+    }
+
+    fn set_auto(address: SocketAddr) {
+        let client = NetClient::connect(address).unwrap();
+        let device = client.open("stub:0").unwrap();
+        let info = device.set_option_auto(&resolution(&device)).unwrap();
+        assert!(info.reload_params);
+    }
+
+    #[test]
+    fn omits_auto_value_from_protocol_3() {
+        let (address, server) = replay(3, |peer| {
+            peer.open();
+            peer.expect(|w| {
+                write_word(w, SANE_NET_CONTROL_OPTION)?;
+                write_word(w, HANDLE)?;
+                write_word(w, RESOLUTION)?;
+                write_word(w, SANE_Action_SANE_ACTION_SET_AUTO as SANE_Word)
+            });
+            reply_to_auto(peer);
+            peer.close();
+        });
+        set_auto(address);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn sends_auto_value_before_protocol_3() {
+        let (address, server) = replay(2, |peer| {
+            peer.open();
+            peer.expect(|w| {
+                write_word(w, SANE_NET_CONTROL_OPTION)?;
+                write_word(w, HANDLE)?;
+                write_word(w, RESOLUTION)?;
+                write_word(w, SANE_Action_SANE_ACTION_SET_AUTO as SANE_Word)?;
+                write_word(w, SANE_Value_Type_SANE_TYPE_INT as SANE_Word)?;
+                write_word(w, 4)?;
+                write_word(w, 1)?;
+                write_word(w, 0)
+            });
+            reply_to_auto(peer);
+            peer.close();
+        });
+        set_auto(address);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn authorizes_and_reads_the_reply_again() {
+        let (address, server) = replay(3, |peer| {
+            peer.expect(|w| {
+                write_word(w, SANE_NET_OPEN)?;
+                write_string(w, Some(cstr(b"stub:0\0")))
+            });
+            peer.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, 0)?;
+                write_string(w, Some(cstr(b"stub\0")))
+            });
+            peer.expect(|w| {
+                write_word(w, SANE_NET_AUTHORIZE)?;
+                write_string(w, Some(cstr(b"stub\0")))?;
+                write_string(w, Some(cstr(b"user\0")))?;
+                write_string(w, Some(cstr(b"secret\0")))
+            });
+            peer.reply(|w| write_word(w, 0));
+            peer.reply(|w| {
+                write_word(w, SANE_Status_SANE_STATUS_ACCESS_DENIED as SANE_Word)?;
+                write_word(w, 0)?;
+                write_string(w, None)
+            });
+        });
+
+        let client = NetClient::connect_with_auth(address, |resource| {
+            assert_eq!(resource, "stub");
+            Some(("user".to_string(), "secret".to_string()))
+        })
+        .unwrap();
+        assert!(matches!(
+            client.open("stub:0").map(|_| ()),
+            Err(SaneError::AccessDenied)
+        ));
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reads_image_data() {
+        let native = if cfg!(target_endian = "little") {
+            LITTLE_ENDIAN
+        } else {
+            BIG_ENDIAN
+        };
+        let (address, server) = replay(3, move |peer| {
+            peer.open();
+            let data = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = data.local_addr().unwrap().port();
+            peer.expect(|w| {
+                write_word(w, SANE_NET_START)?;
+                write_word(w, HANDLE)
+            });
+            peer.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, port as SANE_Word)?;
+                write_word(w, native)?;
+                write_string(w, None)
+            });
+
+            let (mut data, _) = data.accept().unwrap();
+            data.write_all(&[0, 0, 0, 3, 1, 2, 3]).unwrap();
+            data.write_all(&END_OF_DATA.to_be_bytes()).unwrap();
+            data.write_all(&[SANE_Status_SANE_STATUS_EOF as u8])
+                .unwrap();
+
+            peer.expect(|w| {
+                write_word(w, SANE_NET_CANCEL)?;
+                write_word(w, HANDLE)
+            });
+            peer.reply(|w| write_word(w, 0));
+            peer.close();
+        });
+
+        let client = NetClient::connect(address).unwrap();
+        let device = client.open("stub:0").unwrap();
+        let mut scan = device.start().unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(scan.read(&mut buf).unwrap(), Some(3));
+        assert_eq!(buf[..3], [1, 2, 3]);
+        assert_eq!(scan.read(&mut buf).unwrap(), None);
+        drop(scan);
+        drop(device);
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_a_connection_closed_mid_reply() {
+        let (address, server) = replay(3, |peer| {
+            peer.expect(|w| write_word(w, SANE_NET_GET_DEVICES));
+            peer.reply(|w| {
+                write_word(w, GOOD)?;
+                write_word(w, 2)
+            });
+            peer.writer.shutdown(Shutdown::Write).unwrap();
+        });
+
+        let client = NetClient::connect(address).unwrap();
+        assert!(matches!(client.devices(), Err(SaneError::Protocol)));
+        drop(client);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,12 @@
+//! The SANE network protocol. The client reaches scanners shared by `saned` without going through
+//! libsane and its `net` backend, and the server shares local scanners with SANE clients.
+
+mod client;
+mod server;
+mod wire;
+
+pub use client::{NetClient, NetDevice, NetScan};
+pub use server::{Server, ServerConfig};
+
+/// Port `saned` listens on by default.
+pub const DEFAULT_PORT: u16 = 6566;
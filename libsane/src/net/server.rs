@@ -0,0 +1,1024 @@
+use super::wire::*;
+use crate::{
+    device::Device,
+    error::{Result, SaneError},
+    scan::Scan,
+    version::SaneVersion,
+    LibSane,
+};
+use libsane_sys::*;
+use log::{debug, info, warn};
+use std::{
+    convert::TryFrom,
+    ffi::CStr,
+    io::{self, BufReader, BufWriter, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::RangeInclusive,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Size of the buffer handed to `sane_read`, and so the largest record on the data port
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// How long a blocked write on the data port waits before checking for requests
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the client to connect to the data port, unless configured otherwise
+const DEFAULT_DATA_CONNECT_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// How many clients are served at once, unless configured otherwise
+const DEFAULT_MAX_CLIENTS: usize = 16;
+
+/// A host or network in the access list
+#[derive(Debug, Clone)]
+enum AccessEntry {
+    /// `+` allows every host
+    Any,
+    Address(IpAddr),
+    Network(IpAddr, u8),
+    /// Resolved whenever a client connects, so address changes are picked up
+    Host(String),
+}
+
+/// Settings of the server, usually read from a `saned.conf` file.
+///
+/// Every line holds a host name, an IP address or a network in CIDR notation that may connect, or
+/// `+` to allow every host. IPv6 addresses may be put in brackets. The local host may always connect.
+/// The options `data_portrange = <first> - <last>` and `data_connect_timeout = <milliseconds>` control
+/// the data port, and `max_clients = <count>` how many clients are served at once.
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    access: Vec<AccessEntry>,
+    data_ports: Option<RangeInclusive<u16>>,
+    data_connect_timeout: Option<Duration>,
+    max_clients: Option<usize>,
+}
+
+/// Treat IPv4 addresses in IPv6 form like the plain IPv4 address
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+/// Treat IPv4-mapped IPv6 networks like the plain IPv4 network, if the prefix covers the mapping
+fn canonical_network(network: IpAddr, prefix: u8) -> (IpAddr, u8) {
+    match network {
+        IpAddr::V6(v6) if prefix >= 96 => match v6.to_ipv4_mapped() {
+            Some(v4) => (IpAddr::V4(v4), prefix - 96),
+            None => (network, prefix),
+        },
+        _ => (network, prefix),
+    }
+}
+
+fn in_network(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (address, network) {
+        // A short prefix spanning IPv4-mapped and other IPv6 addresses
+        (IpAddr::V4(v4), IpAddr::V6(_)) => {
+            in_network(IpAddr::V6(v4.to_ipv6_mapped()), network, prefix)
+        }
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - prefix.min(32) as u32)
+                .unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - prefix.min(128) as u32)
+                .unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+impl ServerConfig {
+    /// A configuration that only lets the local host connect.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the contents of a `saned.conf` file. Lines that can't be parsed are logged and skipped.
+    pub fn parse(config: &str) -> Self {
+        let mut result = Self::new();
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once('=') {
+                Some((option, value)) => result.set_option(option.trim(), value.trim()),
+                None => {
+                    result = result.allow(line);
+                }
+            }
+        }
+        result
+    }
+
+    /// Read a `saned.conf` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    fn set_option(&mut self, option: &str, value: &str) {
+        match option {
+            "data_portrange" => {
+                let range = value.split_once('-').and_then(|(first, last)| {
+                    Some(first.trim().parse::<u16>().ok()?..=last.trim().parse::<u16>().ok()?)
+                });
+                match range {
+                    Some(range) if !range.is_empty() => self.data_ports = Some(range),
+                    _ => warn!("Invalid data port range \"{}\"", value),
+                }
+            }
+            "data_connect_timeout" => match value.parse() {
+                Ok(timeout) => self.data_connect_timeout = Some(Duration::from_millis(timeout)),
+                Err(_) => warn!("Invalid data connect timeout \"{}\"", value),
+            },
+            "max_clients" => match value.parse() {
+                Ok(max) => self.max_clients = Some(max),
+                Err(_) => warn!("Invalid client limit \"{}\"", value),
+            },
+            _ => warn!("Unknown option \"{}\"", option),
+        }
+    }
+
+    /// Allow a host name, IP address or network in CIDR notation to connect, or every host with `+`.
+    pub fn allow(mut self, host: &str) -> Self {
+        let entry = if host == "+" {
+            AccessEntry::Any
+        } else {
+            let (address, prefix) = match host.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix)),
+                None => (host, None),
+            };
+            let address = address.trim_start_matches('[').trim_end_matches(']');
+
+            match (address.parse::<IpAddr>(), prefix.map(str::parse::<u8>)) {
+                (Ok(address), None) => AccessEntry::Address(canonical(address)),
+                (Ok(address), Some(Ok(prefix))) => {
+                    let (network, prefix) = canonical_network(address, prefix);
+                    AccessEntry::Network(network, prefix)
+                }
+                (Err(_), None) => AccessEntry::Host(host.to_owned()),
+                _ => {
+                    warn!("Invalid access list entry \"{}\"", host);
+                    return self;
+                }
+            }
+        };
+        self.access.push(entry);
+        self
+    }
+
+    /// Use ports from `ports` for the data connections, instead of any free port.
+    pub fn data_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.data_ports = Some(ports);
+        self
+    }
+
+    /// Serve at most `max` clients at once. Further clients are disconnected right away.
+    pub fn max_clients(mut self, max: usize) -> Self {
+        self.max_clients = Some(max);
+        self
+    }
+
+    /// Whether a client connecting from `address` may use the server.
+    pub fn is_allowed(&self, address: IpAddr) -> bool {
+        let address = canonical(address);
+        if address.is_loopback() {
+            return true;
+        }
+
+        self.access.iter().any(|entry| match entry {
+            AccessEntry::Any => true,
+            AccessEntry::Address(allowed) => *allowed == address,
+            AccessEntry::Network(network, prefix) => in_network(address, *network, *prefix),
+            AccessEntry::Host(host) => (host.as_str(), 0)
+                .to_socket_addrs()
+                .map(|mut addresses| addresses.any(|allowed| canonical(allowed.ip()) == address))
+                .unwrap_or(false),
+        })
+    }
+
+    /// Listen for a data connection on `address`, with a port from the configured range
+    fn bind_data_port(&self, address: IpAddr) -> io::Result<TcpListener> {
+        match &self.data_ports {
+            Some(ports) => ports
+                .clone()
+                .find_map(|port| TcpListener::bind((address, port)).ok())
+                .ok_or_else(|| io::ErrorKind::AddrInUse.into()),
+            None => TcpListener::bind((address, 0)),
+        }
+    }
+}
+
+/// Serves the devices of the local SANE library over the SANE network protocol, like `saned`.
+///
+/// Every client gets a session on its own thread, up to the configured number of clients. Backends
+/// asking for credentials are denied, since the server doesn't forward authorization requests to
+/// its clients.
+#[derive(Clone)]
+pub struct Server {
+    sane: Arc<LibSane>,
+    config: Arc<ServerConfig>,
+}
+
+impl Server {
+    pub fn new(sane: Arc<LibSane>, config: ServerConfig) -> Self {
+        Self {
+            sane,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Accept clients on `listener`, serving each on its own thread.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        let max_clients = self.config.max_clients.unwrap_or(DEFAULT_MAX_CLIENTS);
+        let clients = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept a client: {}", e);
+                    continue;
+                }
+            };
+
+            if clients.fetch_add(1, Ordering::AcqRel) >= max_clients {
+                clients.fetch_sub(1, Ordering::AcqRel);
+                warn!(
+                    "Turned away {}, {} clients are connected already",
+                    stream
+                        .peer_addr()
+                        .map_or_else(|_| "a client".to_string(), |peer| peer.to_string()),
+                    max_clients
+                );
+                continue;
+            }
+            let slot = ClientSlot(clients.clone());
+
+            let server = self.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(e) = server.serve_client(stream) {
+                    warn!("Session ended with an error: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Serve a single client on the current thread, until it exits or disconnects.
+    pub fn serve_client(&self, stream: TcpStream) -> Result<()> {
+        let peer = stream.peer_addr()?;
+        info!("{} connected", peer);
+        let result = Session::new(&self.sane, &self.config, stream, peer)?.run();
+        info!("{} disconnected", peer);
+        result
+    }
+}
+
+/// Counts a client as connected until its session ends
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+enum Flow {
+    Continue,
+    Exit,
+    /// The client asked to start a scan on this handle
+    Start(SANE_Word),
+    /// The client asked for the next frame of the running scan
+    NextFrame,
+    /// The client cancelled the running scan
+    Cancel,
+    /// The client closed the device of the running scan
+    Close,
+}
+
+/// The scan running on a session. Its device is taken out of the session's devices meanwhile.
+struct Active<'device, 'sane> {
+    handle: SANE_Word,
+    scan: Scan<'device, 'sane>,
+}
+
+/// The device belonging to a handle the client sent, which may be the one of the running scan
+fn find_device<'a, 'sane>(
+    devices: &'a [Option<Device<'sane>>],
+    handle: SANE_Word,
+    active: Option<&'a Active<'_, 'sane>>,
+) -> Result<&'a Device<'sane>> {
+    match active {
+        Some(active) if active.handle == handle => Ok(active.scan.device()),
+        _ => usize::try_from(handle)
+            .ok()
+            .and_then(|index| devices.get(index))
+            .and_then(Option::as_ref)
+            .ok_or(SaneError::Invalid),
+    }
+}
+
+struct Session<'sane> {
+    sane: &'sane LibSane,
+    config: &'sane ServerConfig,
+    peer: SocketAddr,
+    /// Used to check for requests during image transfers
+    control: TcpStream,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    /// Protocol version of the client
+    protocol: u16,
+    /// Indexed by handle
+    devices: Vec<Option<Device<'sane>>>,
+}
+
+impl<'sane> Session<'sane> {
+    fn new(
+        sane: &'sane LibSane,
+        config: &'sane ServerConfig,
+        stream: TcpStream,
+        peer: SocketAddr,
+    ) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            sane,
+            config,
+            peer,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream.try_clone()?),
+            control: stream,
+            protocol: PROTOCOL_VERSION.build,
+            devices: Vec::new(),
+        })
+    }
+
+    fn run(mut self) -> Result<()> {
+        self.init()?;
+        loop {
+            // A client that simply disconnects ends the session as well
+            let procedure = match read_word(&mut self.reader) {
+                Ok(procedure) => procedure,
+                Err(_) => return Ok(()),
+            };
+            let flow = match self.process(procedure, None)? {
+                Flow::Start(handle) => self.scan(handle)?,
+                flow => flow,
+            };
+            if let Flow::Exit = flow {
+                return Ok(());
+            }
+        }
+    }
+
+    fn init(&mut self) -> Result<()> {
+        if read_word(&mut self.reader)? != SANE_NET_INIT {
+            return Err(SaneError::Invalid);
+        }
+        let version = SaneVersion::from(read_word(&mut self.reader)?);
+        let user = read_string(&mut self.reader)?;
+
+        let result = if !self.config.is_allowed(self.peer.ip()) {
+            warn!("{} is not in the access list", self.peer);
+            Err(SaneError::AccessDenied)
+        } else if version.major != PROTOCOL_VERSION.major || version.build < 2 {
+            warn!("{} speaks unsupported protocol {}", self.peer, version);
+            Err(SaneError::Unsupported)
+        } else {
+            info!("{} is user {:?}", self.peer, user.unwrap_or_default());
+            self.protocol = version.build;
+            Ok(())
+        };
+
+        write_status(&mut self.writer, &result)?;
+        write_word(&mut self.writer, PROTOCOL_VERSION.into())?;
+        self.writer.flush()?;
+        result
+    }
+
+    /// Answer a request. Starting, cancelling and closing the device of a scan are left to the
+    /// caller, which owns the scan.
+    fn process(
+        &mut self,
+        procedure: SANE_Word,
+        active: Option<&Active<'_, 'sane>>,
+    ) -> Result<Flow> {
+        debug!("{} calls procedure {}", self.peer, procedure);
+        let scanning = active.map(|active| active.handle);
+        let mut flow = Flow::Continue;
+        match procedure {
+            SANE_NET_GET_DEVICES => self.get_devices()?,
+            SANE_NET_OPEN => self.open()?,
+            SANE_NET_CLOSE => {
+                let handle = read_word(&mut self.reader)?;
+                if scanning == Some(handle) {
+                    flow = Flow::Close;
+                } else if let Some(device) = usize::try_from(handle)
+                    .ok()
+                    .and_then(|index| self.devices.get_mut(index))
+                {
+                    device.take();
+                }
+                write_word(&mut self.writer, 0)?;
+            }
+            SANE_NET_GET_OPTION_DESCRIPTORS => self.get_option_descriptors(active)?,
+            SANE_NET_CONTROL_OPTION => self.control_option(active)?,
+            SANE_NET_GET_PARAMETERS => {
+                let handle = read_word(&mut self.reader)?;
+                let result = find_device(&self.devices, handle, active)
+                    .and_then(Device::get_params)
+                    .map(SANE_Parameters::from);
+                write_status(&mut self.writer, &result)?;
+                write_parameters(
+                    &mut self.writer,
+                    &result.unwrap_or(SANE_Parameters {
+                        format: Default::default(),
+                        last_frame: Default::default(),
+                        bytes_per_line: Default::default(),
+                        pixels_per_line: Default::default(),
+                        lines: Default::default(),
+                        depth: Default::default(),
+                    }),
+                )?;
+            }
+            SANE_NET_START => {
+                let handle = read_word(&mut self.reader)?;
+                match scanning {
+                    Some(scanning) if scanning == handle => return Ok(Flow::NextFrame),
+                    // Only one scan runs per session
+                    Some(_) => self.write_start(&Err(SaneError::DeviceBusy))?,
+                    None => return Ok(Flow::Start(handle)),
+                }
+            }
+            SANE_NET_CANCEL => {
+                let handle = read_word(&mut self.reader)?;
+                if scanning == Some(handle) {
+                    flow = Flow::Cancel;
+                }
+                write_word(&mut self.writer, 0)?;
+            }
+            SANE_NET_AUTHORIZE => {
+                // The server never asks for authorization, so there is nothing to check
+                for _ in 0..3 {
+                    read_string(&mut self.reader)?;
+                }
+                write_word(&mut self.writer, 0)?;
+            }
+            SANE_NET_EXIT => return Ok(Flow::Exit),
+            _ => {
+                warn!("{} called unknown procedure {}", self.peer, procedure);
+                return Err(SaneError::Invalid);
+            }
+        }
+        self.writer.flush()?;
+        Ok(flow)
+    }
+
+    fn get_devices(&mut self) -> Result<()> {
+        // Only local devices, so servers don't end up serving each other's devices in a loop
+        let result = self.sane.devices(true);
+        write_status(&mut self.writer, &result)?;
+
+        let devices = result.unwrap_or_default();
+        write_word(&mut self.writer, devices.len() as SANE_Word + 1)?;
+        for device in &devices {
+            write_device(&mut self.writer, Some(device))?;
+        }
+        write_device(&mut self.writer, None)
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let name = read_string(&mut self.reader)?.unwrap_or_default();
+        let result = name
+            .to_str()
+            .map_err(|_| SaneError::Invalid)
+            .and_then(|name| self.sane.open_device(name));
+        info!(
+            "{} opens {:?}: {}",
+            self.peer,
+            name,
+            match &result {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            }
+        );
+
+        let handle = match result {
+            Ok(device) => {
+                self.devices.push(Some(device));
+                Ok(self.devices.len() as SANE_Word - 1)
+            }
+            Err(e) => Err(e),
+        };
+        write_status(&mut self.writer, &handle)?;
+        write_word(&mut self.writer, *handle.as_ref().unwrap_or(&0))?;
+        write_string(&mut self.writer, None)
+    }
+
+    fn get_option_descriptors(&mut self, active: Option<&Active<'_, 'sane>>) -> Result<()> {
+        let handle = read_word(&mut self.reader)?;
+
+        // An unknown handle gets an empty list, since the reply has no status
+        let mut descriptors = Vec::new();
+        if let Ok(device) = find_device(&self.devices, handle, active) {
+            for option in 0..device.option_count().unwrap_or(0) {
+                descriptors.push(device.raw_descriptor(option));
+            }
+        }
+
+        write_word(&mut self.writer, descriptors.len() as SANE_Word)?;
+        for descriptor in descriptors {
+            let descriptor =
+                descriptor.map_or(std::ptr::null(), |descriptor| descriptor as *const _);
+            unsafe { write_descriptor(&mut self.writer, descriptor)? };
+        }
+        Ok(())
+    }
+
+    fn control_option(&mut self, active: Option<&Active<'_, 'sane>>) -> Result<()> {
+        let handle = read_word(&mut self.reader)?;
+        let option = read_word(&mut self.reader)?;
+        let action = read_word(&mut self.reader)? as SANE_Action;
+
+        // Protocol version 2 sent a value even for SANE_ACTION_SET_AUTO
+        let (value_type, size, mut buffer) =
+            if self.protocol < 3 || action != SANE_Action_SANE_ACTION_SET_AUTO {
+                let value_type = read_value_type(&mut self.reader)?;
+                let size = read_word(&mut self.reader)?.max(0) as usize;
+                let buffer = read_value(&mut self.reader, value_type)?;
+                (Some(value_type), size, buffer)
+            } else {
+                (None, 0, Vec::new())
+            };
+
+        let result = find_device(&self.devices, handle, active).and_then(|device| {
+            let descriptor = device.raw_descriptor(option).ok_or(SaneError::Invalid)?;
+
+            let value = match value_type {
+                Some(value_type) if action != SANE_Action_SANE_ACTION_SET_AUTO => {
+                    if SANE_Value_Type::from(value_type) != descriptor.type_ {
+                        return Err(SaneError::Invalid);
+                    }
+                    // The backend may write as much as the descriptor's size, whatever the client sent
+                    let length = size
+                        .max(descriptor.size.max(0) as usize)
+                        .max(std::mem::size_of::<SANE_Word>());
+                    buffer.resize(length, 0);
+                    if action == SANE_Action_SANE_ACTION_SET_VALUE
+                        && descriptor.type_ == SANE_Value_Type_SANE_TYPE_STRING
+                    {
+                        CStr::from_bytes_until_nul(&buffer).map_err(|_| SaneError::Invalid)?;
+                    }
+                    buffer.as_mut_ptr() as *mut std::ffi::c_void
+                }
+                _ => std::ptr::null_mut(),
+            };
+
+            device.control_option(option, action, value)
+        });
+
+        buffer.resize(size, 0);
+        write_status(&mut self.writer, &result)?;
+        write_word(&mut self.writer, result.map_or(0, SANE_Int::from))?;
+        match value_type {
+            Some(value_type) => {
+                write_word(
+                    &mut self.writer,
+                    SANE_Value_Type::from(value_type) as SANE_Word,
+                )?;
+                write_word(&mut self.writer, size as SANE_Word)?;
+                write_value(&mut self.writer, value_type, &buffer)?;
+            }
+            None => {
+                write_word(&mut self.writer, 0)?;
+                write_word(&mut self.writer, 0)?;
+                write_word(&mut self.writer, 0)?;
+            }
+        }
+        write_string(&mut self.writer, None)
+    }
+
+    /// Reply to `SANE_NET_START` with the data port, or the reason the scan didn't start
+    fn write_start(&mut self, port: &Result<u16>) -> Result<()> {
+        let native = if cfg!(target_endian = "little") {
+            LITTLE_ENDIAN
+        } else {
+            BIG_ENDIAN
+        };
+        write_status(&mut self.writer, port)?;
+        write_word(&mut self.writer, *port.as_ref().unwrap_or(&0) as SANE_Word)?;
+        write_word(&mut self.writer, native)?;
+        write_string(&mut self.writer, None)?;
+        Ok(self.writer.flush()?)
+    }
+
+    /// Start a scan on `handle` and serve it until the client cancels it
+    fn scan(&mut self, handle: SANE_Word) -> Result<Flow> {
+        let index = usize::try_from(handle).ok();
+        let mut device = match index.and_then(|index| self.devices.get_mut(index)?.take()) {
+            Some(device) => device,
+            None => {
+                self.write_start(&Err(SaneError::Invalid))?;
+                return Ok(Flow::Continue);
+            }
+        };
+
+        let flow = match device.start() {
+            Ok(scan) => self.serve_scan(Active { handle, scan }),
+            Err(e) => self.write_start(&Err(e)).map(|_| Flow::Continue),
+        };
+
+        // The scan is over, so the device goes back unless the client closed it
+        match flow {
+            Ok(Flow::Close) => Ok(Flow::Continue),
+            flow => {
+                if let Some(index) = index {
+                    self.devices[index] = Some(device);
+                }
+                flow
+            }
+        }
+    }
+
+    /// Send the frames of a scan, answering requests in between. Dropping `active` cancels the scan.
+    fn serve_scan(&mut self, mut active: Active<'_, 'sane>) -> Result<Flow> {
+        let mut flow = self.send_frame(&mut active)?;
+        loop {
+            flow = match flow {
+                Flow::Continue => {
+                    let procedure = match read_word(&mut self.reader) {
+                        Ok(procedure) => procedure,
+                        Err(_) => return Ok(Flow::Exit),
+                    };
+                    self.process(procedure, Some(&active))?
+                }
+                Flow::NextFrame => match active.scan.next_frame() {
+                    Ok(()) => self.send_frame(&mut active)?,
+                    Err(e) => {
+                        self.write_start(&Err(e))?;
+                        Flow::Continue
+                    }
+                },
+                // Only requested while no scan is running
+                Flow::Start(_) => unreachable!(),
+                Flow::Cancel | Flow::Close | Flow::Exit => return Ok(flow),
+            };
+        }
+    }
+
+    /// Open a data port for the current frame and send its image data
+    fn send_frame(&mut self, active: &mut Active<'_, 'sane>) -> Result<Flow> {
+        let address = self.control.local_addr()?.ip();
+        let listener = match self.config.bind_data_port(address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!("Failed to open a data port: {}", e);
+                self.write_start(&Err(e.into()))?;
+                return Ok(Flow::Cancel);
+            }
+        };
+        self.write_start(&Ok(listener.local_addr()?.port()))?;
+
+        let mut data = match self.accept_data(listener) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("{} did not connect to the data port: {}", self.peer, e);
+                return Ok(Flow::Cancel);
+            }
+        };
+        data.set_write_timeout(Some(POLL_INTERVAL))?;
+        self.transfer(active, &mut data)
+    }
+
+    /// Wait for the client to connect to the data port
+    fn accept_data(&self, listener: TcpListener) -> io::Result<TcpStream> {
+        let timeout = self
+            .config
+            .data_connect_timeout
+            .unwrap_or(DEFAULT_DATA_CONNECT_TIMEOUT);
+        let deadline = Instant::now() + timeout;
+
+        listener.set_nonblocking(true)?;
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) if address.ip() == self.peer.ip() => {
+                    stream.set_nonblocking(false)?;
+                    return Ok(stream);
+                }
+                Ok((_, address)) => warn!("Rejected data connection from {}", address),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Send the image data of the current frame, while still answering requests
+    fn transfer(&mut self, active: &mut Active<'_, 'sane>, data: &mut TcpStream) -> Result<Flow> {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        let (status, flow) = loop {
+            match self.process_pending(active)? {
+                Flow::Continue => (),
+                Flow::Exit => return Ok(Flow::Exit),
+                flow => break (SaneError::Cancelled, flow),
+            }
+
+            match active.scan.read(&mut buffer) {
+                Ok(Some(length)) if length > 0 => {
+                    let mut record = (length as u32).to_be_bytes().to_vec();
+                    record.extend_from_slice(&buffer[..length]);
+                    match self.send(active, data, &record)? {
+                        Flow::Continue => (),
+                        Flow::Exit => return Ok(Flow::Exit),
+                        flow => break (SaneError::Cancelled, flow),
+                    }
+                }
+                Ok(Some(_)) => (),
+                Ok(None) => break (SaneError::EOF, Flow::Continue),
+                Err(e) => break (e, Flow::Continue),
+            }
+        };
+
+        let mut end = END_OF_DATA.to_be_bytes().to_vec();
+        end.push(status.to_retcode() as u8);
+        match self.send(active, data, &end)? {
+            Flow::Continue => Ok(flow),
+            other => Ok(other),
+        }
+    }
+
+    /// Write to the data port, answering requests whenever the client isn't reading
+    fn send(
+        &mut self,
+        active: &Active<'_, 'sane>,
+        data: &mut TcpStream,
+        mut bytes: &[u8],
+    ) -> Result<Flow> {
+        while !bytes.is_empty() {
+            match data.write(bytes) {
+                Ok(0) => return Err(SaneError::Network(io::ErrorKind::WriteZero)),
+                Ok(written) => bytes = &bytes[written..],
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    match self.process_pending(active)? {
+                        Flow::Continue => (),
+                        flow => return Ok(flow),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Answer the requests that have arrived on the control connection, without waiting for more.
+    /// Stops at the first request that affects the running scan.
+    fn process_pending(&mut self, active: &Active<'_, 'sane>) -> Result<Flow> {
+        while self.control_pending()? {
+            let procedure = read_word(&mut self.reader)?;
+            match self.process(procedure, Some(active))? {
+                Flow::Continue => (),
+                // The current frame isn't complete yet
+                Flow::NextFrame => self.write_start(&Err(SaneError::DeviceBusy))?,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn control_pending(&mut self) -> Result<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(true);
+        }
+
+        self.control.set_nonblocking(true)?;
+        // A closed connection counts as pending, so reading from it ends the session
+        let pending = match self.control.peek(&mut [0u8]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.into()),
+        };
+        self.control.set_nonblocking(false)?;
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::NetClient;
+    use std::sync::OnceLock;
+
+    /// Only one `LibSane` may exist at a time, so the tests share it
+    fn sane() -> Arc<LibSane> {
+        static SANE: OnceLock<Arc<LibSane>> = OnceLock::new();
+        SANE.get_or_init(|| Arc::new(LibSane::init(None).unwrap()))
+            .clone()
+    }
+
+    /// Serve a single client on a listener of its own
+    fn serve_one(config: ServerConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(sane(), config);
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            server.serve_client(stream).unwrap();
+        });
+        address
+    }
+
+    #[test]
+    #[ignore = "needs a SANE library with devices"]
+    fn serves_the_local_device_list() {
+        let client = NetClient::connect(serve_one(ServerConfig::new())).unwrap();
+        assert_eq!(client.version(), PROTOCOL_VERSION);
+        assert_eq!(client.devices().unwrap(), sane().devices(true).unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs a SANE library with devices"]
+    fn reports_errors_of_the_local_library() {
+        let client = NetClient::connect(serve_one(ServerConfig::new())).unwrap();
+        assert!(matches!(
+            client.open("no-such-backend:0").map(|_| ()),
+            Err(SaneError::Invalid)
+        ));
+        // The session goes on after a failed request
+        assert!(client.devices().is_ok());
+    }
+
+    #[test]
+    #[ignore = "needs a SANE library with devices"]
+    fn turns_away_clients_beyond_the_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(sane(), ServerConfig::new().max_clients(1));
+        thread::spawn(move || server.serve(listener));
+
+        let first = NetClient::connect(address).unwrap();
+        assert!(NetClient::connect(address).is_err());
+
+        // The slot frees up once the first session has ended
+        drop(first);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while NetClient::connect(address).is_err() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_config_files() {
+        let config = ServerConfig::parse(
+            "# saned.conf\n\
+             \n\
+             data_portrange = 10000 - 10100\n\
+             data_connect_timeout = 2500 # milliseconds\n\
+             max_clients=4\n\
+             \t192.168.1.0/24   \n\
+             [2001:db8::1]\n\
+             # 10.0.0.1\n\
+             scanner.example\n",
+        );
+        assert_eq!(config.data_ports, Some(10000..=10100));
+        assert_eq!(
+            config.data_connect_timeout,
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(config.max_clients, Some(4));
+        assert_eq!(config.access.len(), 3);
+        assert!(
+            matches!(config.access[2], AccessEntry::Host(ref host) if host == "scanner.example")
+        );
+
+        assert!(config.is_allowed(ip("192.168.1.20")));
+        assert!(config.is_allowed(ip("2001:db8::1")));
+        assert!(!config.is_allowed(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn skips_invalid_lines() {
+        let config = ServerConfig::parse(
+            "data_portrange = 10100 - 10000\n\
+             data_connect_timeout = soon\n\
+             max_clients = -1\n\
+             unknown = 1\n\
+             10.0.0.0/abc\n\
+             [2001:db8::]/x\n",
+        );
+        assert!(config.data_ports.is_none());
+        assert!(config.data_connect_timeout.is_none());
+        assert!(config.max_clients.is_none());
+        assert!(config.access.is_empty());
+    }
+
+    #[test]
+    fn only_allows_the_local_host_by_default() {
+        let config = ServerConfig::new();
+        assert!(config.is_allowed(ip("127.0.0.1")));
+        assert!(config.is_allowed(ip("127.1.2.3")));
+        assert!(config.is_allowed(ip("::1")));
+        assert!(config.is_allowed(ip("::ffff:127.0.0.1")));
+        assert!(!config.is_allowed(ip("192.168.1.20")));
+        assert!(!config.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allows_every_host_with_plus() {
+        let config = ServerConfig::parse("+\n");
+        assert!(config.is_allowed(ip("192.168.1.20")));
+        assert!(config.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allows_networks_by_mask() {
+        let config = ServerConfig::new()
+            .allow("192.168.1.0/24")
+            .allow("10.1.2.3/32")
+            .allow("[2001:db8::]/32");
+        assert!(config.is_allowed(ip("192.168.1.1")));
+        assert!(config.is_allowed(ip("192.168.1.255")));
+        assert!(!config.is_allowed(ip("192.168.2.1")));
+        assert!(config.is_allowed(ip("10.1.2.3")));
+        assert!(!config.is_allowed(ip("10.1.2.4")));
+        assert!(config.is_allowed(ip("2001:db8:ffff::1")));
+        assert!(!config.is_allowed(ip("2001:db9::1")));
+
+        let config = ServerConfig::new().allow("0.0.0.0/0");
+        assert!(config.is_allowed(ip("203.0.113.7")));
+        assert!(!config.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allows_addresses_with_and_without_brackets() {
+        let config = ServerConfig::new()
+            .allow("[2001:db8::1]")
+            .allow("2001:db8::2");
+        assert!(config.is_allowed(ip("2001:db8::1")));
+        assert!(config.is_allowed(ip("2001:db8::2")));
+        assert!(!config.is_allowed(ip("2001:db8::3")));
+    }
+
+    #[test]
+    fn matches_ipv4_mapped_peers_against_ipv4_rules() {
+        let config = ServerConfig::new()
+            .allow("192.168.1.0/24")
+            .allow("10.0.0.1");
+        assert!(config.is_allowed(ip("::ffff:192.168.1.20")));
+        assert!(config.is_allowed(ip("::ffff:10.0.0.1")));
+        assert!(!config.is_allowed(ip("::ffff:192.168.2.20")));
+    }
+
+    #[test]
+    fn matches_ipv4_peers_against_mapped_rules() {
+        let config = ServerConfig::new()
+            .allow("[::ffff:192.168.1.0]/120")
+            .allow("::ffff:10.0.0.1");
+        assert!(config.is_allowed(ip("192.168.1.20")));
+        assert!(config.is_allowed(ip("::ffff:192.168.1.20")));
+        assert!(config.is_allowed(ip("10.0.0.1")));
+        assert!(!config.is_allowed(ip("192.168.2.20")));
+
+        // Prefixes shorter than the mapping also cover other IPv6 addresses
+        let config = ServerConfig::new().allow("::/64");
+        assert!(config.is_allowed(ip("192.168.1.20")));
+        assert!(config.is_allowed(ip("::2")));
+        assert!(!config.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn compares_networks_of_one_family() {
+        assert!(in_network(ip("10.0.0.1"), ip("10.0.0.0"), 8));
+        assert!(!in_network(ip("11.0.0.1"), ip("10.0.0.0"), 8));
+        assert!(in_network(ip("11.0.0.1"), ip("10.0.0.0"), 0));
+        assert!(!in_network(ip("2001:db8::1"), ip("10.0.0.0"), 0));
+        assert!(in_network(ip("10.0.0.1"), ip("10.0.0.1"), 40));
+    }
+}
@@ -0,0 +1,636 @@
+//! Encoding of the SANE network protocol. Words are sent big-endian, strings as their length
+//! including the terminator followed by their characters, and pointers as a null flag followed by
+//! the value.
+
+use crate::{
+    device::bool_from_word,
+    device_list::DeviceInfo,
+    error::{Result, SaneError},
+    fixed::SaneFixed,
+    option_descriptor::{Capabilities, Constraint, OptionDescriptor, Unit, ValueType},
+    version::SaneVersion,
+};
+use libsane_sys::*;
+use std::{
+    convert::{TryFrom, TryInto},
+    ffi::{CStr, CString},
+    io::{Read, Write},
+    num::NonZeroI32,
+};
+
+/// Version code exchanged in `SANE_NET_INIT`, whose build number is the protocol version
+pub(crate) const PROTOCOL_VERSION: SaneVersion = SaneVersion {
+    major: 1,
+    minor: 0,
+    build: 3,
+};
+
+// Remote procedure numbers
+pub(crate) const SANE_NET_INIT: SANE_Word = 0;
+pub(crate) const SANE_NET_GET_DEVICES: SANE_Word = 1;
+pub(crate) const SANE_NET_OPEN: SANE_Word = 2;
+pub(crate) const SANE_NET_CLOSE: SANE_Word = 3;
+pub(crate) const SANE_NET_GET_OPTION_DESCRIPTORS: SANE_Word = 4;
+pub(crate) const SANE_NET_CONTROL_OPTION: SANE_Word = 5;
+pub(crate) const SANE_NET_GET_PARAMETERS: SANE_Word = 6;
+pub(crate) const SANE_NET_START: SANE_Word = 7;
+pub(crate) const SANE_NET_CANCEL: SANE_Word = 8;
+pub(crate) const SANE_NET_AUTHORIZE: SANE_Word = 9;
+pub(crate) const SANE_NET_EXIT: SANE_Word = 10;
+
+/// Byte orders announced in the `SANE_NET_START` reply
+pub(crate) const LITTLE_ENDIAN: SANE_Word = 0x1234;
+pub(crate) const BIG_ENDIAN: SANE_Word = 0x4321;
+
+/// Length of a data port record that marks the end of the image data
+pub(crate) const END_OF_DATA: u32 = 0xffff_ffff;
+
+pub(crate) fn read_word<R: Read>(reader: &mut R) -> Result<SANE_Word> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(SANE_Word::from_be_bytes(bytes))
+}
+
+pub(crate) fn write_word<W: Write>(writer: &mut W, word: SANE_Word) -> Result<()> {
+    Ok(writer.write_all(&word.to_be_bytes())?)
+}
+
+pub(crate) fn read_bool<R: Read>(reader: &mut R) -> Result<bool> {
+    bool_from_word(read_word(reader)?)
+}
+
+/// Read the length of an array, rejecting negative ones
+pub(crate) fn read_length<R: Read>(reader: &mut R) -> Result<usize> {
+    usize::try_from(read_word(reader)?).map_err(|_| SaneError::Invalid)
+}
+
+/// Read exactly `length` bytes, growing the buffer only as data arrives
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err(SaneError::Protocol);
+    }
+    Ok(bytes)
+}
+
+/// Read a string, where `None` stands for a null pointer
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> Result<Option<CString>> {
+    let length = read_length(reader)?;
+    if length == 0 {
+        return Ok(None);
+    }
+
+    let bytes = read_bytes(reader, length)?;
+    Ok(Some(match CStr::from_bytes_until_nul(&bytes) {
+        Ok(string) => string.to_owned(),
+        Err(_) => CString::new(bytes).map_err(|_| SaneError::Invalid)?,
+    }))
+}
+
+pub(crate) fn write_string<W: Write>(writer: &mut W, string: Option<&CStr>) -> Result<()> {
+    match string {
+        Some(string) => {
+            let bytes = string.to_bytes_with_nul();
+            write_word(writer, bytes.len() as SANE_Word)?;
+            Ok(writer.write_all(bytes)?)
+        }
+        None => write_word(writer, 0),
+    }
+}
+
+/// Read the null flag in front of a pointer's value, returning whether a value follows
+pub(crate) fn read_pointer<R: Read>(reader: &mut R) -> Result<bool> {
+    Ok(!read_bool(reader)?)
+}
+
+/// Read a status word, turning anything but `SANE_STATUS_GOOD` into an error
+pub(crate) fn read_status<R: Read>(reader: &mut R) -> Result<Result<()>> {
+    Ok(SaneError::from_retcode(read_word(reader)? as SANE_Status))
+}
+
+/// Write an option value, held in `buffer` the way `sane_control_option` expects it
+pub(crate) fn write_value<W: Write>(
+    writer: &mut W,
+    value_type: ValueType,
+    buffer: &[u8],
+) -> Result<()> {
+    match value_type {
+        ValueType::Bool | ValueType::Int | ValueType::Fixed => {
+            let words = buffer.chunks_exact(std::mem::size_of::<SANE_Word>());
+            write_word(writer, words.len() as SANE_Word)?;
+            for word in words {
+                write_word(
+                    writer,
+                    SANE_Word::from_ne_bytes([word[0], word[1], word[2], word[3]]),
+                )?;
+            }
+            Ok(())
+        }
+        ValueType::String => {
+            write_word(writer, buffer.len() as SANE_Word)?;
+            Ok(writer.write_all(buffer)?)
+        }
+        ValueType::Button | ValueType::Group => write_word(writer, 0),
+    }
+}
+
+/// Read an option value into the buffer layout `sane_control_option` uses
+pub(crate) fn read_value<R: Read>(reader: &mut R, value_type: ValueType) -> Result<Vec<u8>> {
+    let length = read_length(reader)?;
+    match value_type {
+        ValueType::Bool | ValueType::Int | ValueType::Fixed => {
+            let mut buffer = Vec::new();
+            for _ in 0..length {
+                buffer.extend_from_slice(&read_word(reader)?.to_ne_bytes());
+            }
+            Ok(buffer)
+        }
+        ValueType::String => read_bytes(reader, length),
+        ValueType::Button | ValueType::Group => Ok(Vec::new()),
+    }
+}
+
+pub(crate) fn read_value_type<R: Read>(reader: &mut R) -> Result<ValueType> {
+    (read_word(reader)? as SANE_Value_Type).try_into()
+}
+
+/// Read a `SANE_Device` pointer, as found in the `SANE_NET_GET_DEVICES` reply
+pub(crate) fn read_device<R: Read>(reader: &mut R) -> Result<Option<DeviceInfo>> {
+    if !read_pointer(reader)? {
+        return Ok(None);
+    }
+
+    let mut field = || -> Result<String> {
+        Ok(read_string(reader)?
+            .map(|string| string.to_string_lossy().into_owned())
+            .unwrap_or_default())
+    };
+    Ok(Some(DeviceInfo {
+        name: field()?,
+        vendor: field()?,
+        model: field()?,
+        type_: field()?,
+    }))
+}
+
+pub(crate) fn read_parameters<R: Read>(reader: &mut R) -> Result<SANE_Parameters> {
+    Ok(SANE_Parameters {
+        format: read_word(reader)? as SANE_Frame,
+        last_frame: read_word(reader)?,
+        bytes_per_line: read_word(reader)?,
+        pixels_per_line: read_word(reader)?,
+        lines: read_word(reader)?,
+        depth: read_word(reader)?,
+    })
+}
+
+/// An option descriptor received from the server, which owns what a C descriptor points to.
+#[derive(Debug)]
+pub(crate) struct RemoteDescriptor {
+    name: Option<CString>,
+    title: Option<CString>,
+    description: Option<CString>,
+    value_type: ValueType,
+    unit: Unit,
+    size: SANE_Int,
+    capabilities: Capabilities,
+    constraint: RemoteConstraint,
+}
+
+#[derive(Debug)]
+enum RemoteConstraint {
+    None,
+    Range {
+        min: SANE_Word,
+        max: SANE_Word,
+        quant: SANE_Word,
+    },
+    List(Vec<SANE_Word>),
+    FixedList(Vec<SaneFixed>),
+    StringList(Vec<CString>),
+}
+
+impl RemoteDescriptor {
+    /// Borrow this as the descriptor of option `number`
+    pub(crate) fn descriptor(&self, number: SANE_Int) -> OptionDescriptor<'_> {
+        let constraint = match &self.constraint {
+            RemoteConstraint::None => Constraint::None,
+            RemoteConstraint::Range { min, max, quant } => match self.value_type {
+                ValueType::Fixed => Constraint::FixedRange {
+                    min: SaneFixed(*min),
+                    max: SaneFixed(*max),
+                    quant: NonZeroI32::new(*quant).map(|q| SaneFixed(q.get())),
+                },
+                _ => Constraint::Range {
+                    min: *min,
+                    max: *max,
+                    quant: NonZeroI32::new(*quant),
+                },
+            },
+            RemoteConstraint::List(list) => Constraint::List(list),
+            RemoteConstraint::FixedList(list) => Constraint::FixedList(list),
+            RemoteConstraint::StringList(list) => {
+                Constraint::StringList(list.iter().map(CString::as_c_str).collect())
+            }
+        };
+
+        OptionDescriptor {
+            number,
+            name: self.name.as_deref(),
+            title: self.title.as_deref(),
+            description: self.description.as_deref(),
+            value_type: self.value_type,
+            capabilities: self.capabilities,
+            unit: self.unit,
+            size: self.size,
+            constraint,
+        }
+    }
+}
+
+/// Read a `SANE_Option_Descriptor` pointer, as found in the `SANE_NET_GET_OPTION_DESCRIPTORS` reply
+pub(crate) fn read_descriptor<R: Read>(reader: &mut R) -> Result<Option<RemoteDescriptor>> {
+    if !read_pointer(reader)? {
+        return Ok(None);
+    }
+
+    let name = read_string(reader)?;
+    let title = read_string(reader)?;
+    let description = read_string(reader)?;
+    let value_type = read_value_type(reader)?;
+    let unit = (read_word(reader)? as SANE_Unit).try_into()?;
+    let size = read_word(reader)?;
+    let capabilities = Capabilities::from(read_word(reader)?);
+
+    let constraint_type = read_word(reader)? as SANE_Constraint_Type;
+    let constraint = match constraint_type {
+        SANE_Constraint_Type_SANE_CONSTRAINT_NONE => RemoteConstraint::None,
+        SANE_Constraint_Type_SANE_CONSTRAINT_RANGE => {
+            if read_pointer(reader)? {
+                RemoteConstraint::Range {
+                    min: read_word(reader)?,
+                    max: read_word(reader)?,
+                    quant: read_word(reader)?,
+                }
+            } else {
+                RemoteConstraint::None
+            }
+        }
+        SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST => {
+            // The array holds the whole C list, starting with its length
+            let length = read_length(reader)?;
+            let mut words = Vec::new();
+            for _ in 0..length {
+                words.push(read_word(reader)?);
+            }
+            let count = words.first().map_or(0, |&count| count.max(0) as usize);
+            let list = words.into_iter().skip(1).take(count);
+            match value_type {
+                ValueType::Fixed => RemoteConstraint::FixedList(list.map(SaneFixed).collect()),
+                _ => RemoteConstraint::List(list.collect()),
+            }
+        }
+        SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST => {
+            // The array includes the null pointer that terminates the C list
+            let length = read_length(reader)?;
+            let mut strings = Vec::new();
+            for _ in 0..length {
+                strings.extend(read_string(reader)?);
+            }
+            RemoteConstraint::StringList(strings)
+        }
+        _ => return Err(SaneError::UnknownConstraint(constraint_type as i32)),
+    };
+
+    Ok(Some(RemoteDescriptor {
+        name,
+        title,
+        description,
+        value_type,
+        unit,
+        size,
+        capabilities,
+        constraint,
+    }))
+}
+
+/// Write the status of a result, `SANE_STATUS_GOOD` for `Ok`
+pub(crate) fn write_status<W: Write, T>(writer: &mut W, result: &Result<T>) -> Result<()> {
+    let status = match result {
+        Ok(_) => SANE_Status_SANE_STATUS_GOOD,
+        Err(e) => e.to_retcode(),
+    };
+    write_word(writer, status as SANE_Word)
+}
+
+/// Write a `SANE_Device` pointer, as found in the `SANE_NET_GET_DEVICES` reply
+pub(crate) fn write_device<W: Write>(writer: &mut W, device: Option<&DeviceInfo>) -> Result<()> {
+    let device = match device {
+        Some(device) => device,
+        None => return write_word(writer, SANE_TRUE as SANE_Word),
+    };
+
+    write_word(writer, SANE_FALSE as SANE_Word)?;
+    for field in &[&device.name, &device.vendor, &device.model, &device.type_] {
+        write_string(writer, CString::new(field.as_str()).ok().as_deref())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_parameters<W: Write>(writer: &mut W, params: &SANE_Parameters) -> Result<()> {
+    write_word(writer, params.format as SANE_Word)?;
+    write_word(writer, params.last_frame)?;
+    write_word(writer, params.bytes_per_line)?;
+    write_word(writer, params.pixels_per_line)?;
+    write_word(writer, params.lines)?;
+    write_word(writer, params.depth)
+}
+
+unsafe fn optional_cstr<'a>(ptr: SANE_String_Const) -> Option<&'a CStr> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr))
+    }
+}
+
+/// Write a `SANE_Option_Descriptor` pointer, as found in the `SANE_NET_GET_OPTION_DESCRIPTORS` reply.
+///
+/// The descriptor is sent as the backend describes it, since `OptionDescriptor` doesn't keep every
+/// capability bit.
+///
+/// # Safety
+/// `descriptor` must be null or point to a valid descriptor.
+pub(crate) unsafe fn write_descriptor<W: Write>(
+    writer: &mut W,
+    descriptor: *const SANE_Option_Descriptor,
+) -> Result<()> {
+    if descriptor.is_null() {
+        return write_word(writer, SANE_TRUE as SANE_Word);
+    }
+    let descriptor = &*descriptor;
+
+    write_word(writer, SANE_FALSE as SANE_Word)?;
+    write_string(writer, optional_cstr(descriptor.name))?;
+    write_string(writer, optional_cstr(descriptor.title))?;
+    write_string(writer, optional_cstr(descriptor.desc))?;
+    write_word(writer, descriptor.type_ as SANE_Word)?;
+    write_word(writer, descriptor.unit as SANE_Word)?;
+    write_word(writer, descriptor.size)?;
+    write_word(writer, descriptor.cap)?;
+    write_word(writer, descriptor.constraint_type as SANE_Word)?;
+
+    match descriptor.constraint_type {
+        SANE_Constraint_Type_SANE_CONSTRAINT_RANGE => {
+            let range = descriptor.constraint.range;
+            if range.is_null() {
+                return write_word(writer, SANE_TRUE as SANE_Word);
+            }
+            write_word(writer, SANE_FALSE as SANE_Word)?;
+            write_word(writer, (*range).min)?;
+            write_word(writer, (*range).max)?;
+            write_word(writer, (*range).quant)
+        }
+        SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST => {
+            let list = descriptor.constraint.word_list;
+            let length = if list.is_null() {
+                0
+            } else {
+                (*list).max(0) + 1
+            };
+            write_word(writer, length)?;
+            for i in 0..length as usize {
+                write_word(writer, *list.add(i))?;
+            }
+            Ok(())
+        }
+        SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST => {
+            let list = descriptor.constraint.string_list;
+            let mut strings = Vec::new();
+            while !list.is_null() && !(*list.add(strings.len())).is_null() {
+                strings.push(CStr::from_ptr(*list.add(strings.len())));
+            }
+            // Including the null pointer that terminates the list
+            write_word(writer, strings.len() as SANE_Word + 1)?;
+            for string in strings {
+                write_string(writer, Some(string))?;
+            }
+            write_string(writer, None)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(write: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn cstr(bytes: &[u8]) -> &CStr {
+        CStr::from_bytes_with_nul(bytes).unwrap()
+    }
+
+    #[test]
+    fn sends_words_big_endian() {
+        let bytes = encode(|w| write_word(w, 0x0102_0304));
+        assert_eq!(bytes, [1, 2, 3, 4]);
+        assert_eq!(read_word(&mut &bytes[..]).unwrap(), 0x0102_0304);
+        assert_eq!(
+            read_word(&mut &encode(|w| write_word(w, -2))[..]).unwrap(),
+            -2
+        );
+    }
+
+    #[test]
+    fn round_trips_strings() {
+        let bytes = encode(|w| write_string(w, Some(cstr(b"Color\0"))));
+        assert_eq!(bytes, b"\0\0\0\x06Color\0");
+        let string = read_string(&mut &bytes[..]).unwrap();
+        assert_eq!(string.as_deref(), Some(cstr(b"Color\0")));
+
+        let bytes = encode(|w| write_string(w, None));
+        assert_eq!(read_string(&mut &bytes[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn accepts_strings_without_terminator() {
+        let string = read_string(&mut &b"\0\0\0\x03abc"[..]).unwrap();
+        assert_eq!(string.as_deref(), Some(cstr(b"abc\0")));
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let words: Vec<u8> = [5, -1].iter().flat_map(|w: &i32| w.to_ne_bytes()).collect();
+        let bytes = encode(|w| write_value(w, ValueType::Int, &words));
+        assert_eq!(read_value(&mut &bytes[..], ValueType::Int).unwrap(), words);
+
+        let bytes = encode(|w| write_value(w, ValueType::String, b"Gray\0\0\0\0"));
+        assert_eq!(
+            read_value(&mut &bytes[..], ValueType::String).unwrap(),
+            b"Gray\0\0\0\0"
+        );
+
+        let bytes = encode(|w| write_value(w, ValueType::Button, &[]));
+        assert!(read_value(&mut &bytes[..], ValueType::Button)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn round_trips_devices() {
+        let device = DeviceInfo {
+            name: "stub:0".to_string(),
+            vendor: "Stub".to_string(),
+            model: "Flatbed".to_string(),
+            type_: "flatbed scanner".to_string(),
+        };
+        let bytes = encode(|w| write_device(w, Some(&device)));
+        assert_eq!(read_device(&mut &bytes[..]).unwrap(), Some(device));
+
+        let bytes = encode(|w| write_device(w, None));
+        assert_eq!(read_device(&mut &bytes[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn round_trips_parameters() {
+        let params = SANE_Parameters {
+            format: SANE_Frame_SANE_FRAME_RGB,
+            last_frame: SANE_TRUE as SANE_Word,
+            bytes_per_line: 300,
+            pixels_per_line: 100,
+            lines: -1,
+            depth: 8,
+        };
+        let bytes = encode(|w| write_parameters(w, &params));
+        let read = read_parameters(&mut &bytes[..]).unwrap();
+        assert_eq!(
+            (read.format, read.last_frame, read.bytes_per_line),
+            (params.format, params.last_frame, params.bytes_per_line)
+        );
+        assert_eq!(
+            (read.pixels_per_line, read.lines, read.depth),
+            (params.pixels_per_line, params.lines, params.depth)
+        );
+    }
+
+    fn c_descriptor(
+        type_: SANE_Value_Type,
+        constraint_type: SANE_Constraint_Type,
+        constraint: SANE_Option_Descriptor__bindgen_ty_1,
+    ) -> SANE_Option_Descriptor {
+        SANE_Option_Descriptor {
+            name: b"resolution\0".as_ptr() as SANE_String_Const,
+            title: b"Resolution\0".as_ptr() as SANE_String_Const,
+            desc: std::ptr::null(),
+            type_,
+            unit: SANE_Unit_SANE_UNIT_DPI,
+            size: std::mem::size_of::<SANE_Word>() as SANE_Int,
+            cap: (SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT | SANE_CAP_AUTOMATIC) as SANE_Int,
+            constraint_type,
+            constraint,
+        }
+    }
+
+    fn round_trip(option: &SANE_Option_Descriptor) -> RemoteDescriptor {
+        let bytes = encode(|w| unsafe { write_descriptor(w, option) });
+        let mut reader = &bytes[..];
+        let descriptor = read_descriptor(&mut reader).unwrap().unwrap();
+        assert!(reader.is_empty());
+        descriptor
+    }
+
+    #[test]
+    fn round_trips_descriptors_with_ranges() {
+        let range = SANE_Range {
+            min: 75,
+            max: 1200,
+            quant: 25,
+        };
+        let option = c_descriptor(
+            SANE_Value_Type_SANE_TYPE_INT,
+            SANE_Constraint_Type_SANE_CONSTRAINT_RANGE,
+            SANE_Option_Descriptor__bindgen_ty_1 { range: &range },
+        );
+        let remote = round_trip(&option);
+        let descriptor = remote.descriptor(3);
+
+        assert_eq!(descriptor.number, 3);
+        assert_eq!(descriptor.name, Some(cstr(b"resolution\0")));
+        assert_eq!(descriptor.title, Some(cstr(b"Resolution\0")));
+        assert_eq!(descriptor.description, None);
+        assert!(matches!(descriptor.value_type, ValueType::Int));
+        assert_eq!(descriptor.unit, Unit::DPI);
+        assert_eq!(descriptor.size, 4);
+        assert!(descriptor.capabilities.automatic && !descriptor.capabilities.inactive);
+        assert!(matches!(
+            descriptor.constraint,
+            Constraint::Range { min: 75, max: 1200, quant: Some(q) } if q.get() == 25
+        ));
+    }
+
+    #[test]
+    fn round_trips_descriptors_with_lists() {
+        let words = [3, 150, 300, 600];
+        let option = c_descriptor(
+            SANE_Value_Type_SANE_TYPE_INT,
+            SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST,
+            SANE_Option_Descriptor__bindgen_ty_1 {
+                word_list: words.as_ptr(),
+            },
+        );
+        let remote = round_trip(&option);
+        assert!(
+            matches!(remote.descriptor(1).constraint, Constraint::List(list) if list == [150, 300, 600])
+        );
+
+        let strings = [
+            b"Color\0".as_ptr() as SANE_String_Const,
+            b"Gray\0".as_ptr() as SANE_String_Const,
+            std::ptr::null(),
+        ];
+        let option = c_descriptor(
+            SANE_Value_Type_SANE_TYPE_STRING,
+            SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST,
+            SANE_Option_Descriptor__bindgen_ty_1 {
+                string_list: strings.as_ptr(),
+            },
+        );
+        let remote = round_trip(&option);
+        assert!(matches!(
+            remote.descriptor(1).constraint,
+            Constraint::StringList(list) if list == [cstr(b"Color\0"), cstr(b"Gray\0")]
+        ));
+    }
+
+    #[test]
+    fn round_trips_missing_descriptors() {
+        let bytes = encode(|w| unsafe { write_descriptor(w, std::ptr::null()) });
+        assert!(read_descriptor(&mut &bytes[..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_truncated_messages() {
+        assert!(matches!(
+            read_word(&mut &[0u8, 0][..]),
+            Err(SaneError::Protocol)
+        ));
+        assert!(matches!(
+            read_string(&mut &b"\0\0\0\x06Col"[..]),
+            Err(SaneError::Protocol)
+        ));
+    }
+
+    #[test]
+    fn rejects_negative_lengths() {
+        assert!(matches!(
+            read_string(&mut &(-1i32).to_be_bytes()[..]),
+            Err(SaneError::Invalid)
+        ));
+    }
+}
@@ -1,5 +1,10 @@
-use crate::{device::Device, error::SaneError};
+use crate::{
+    device::{Device, Value},
+    error::{Result, SaneError},
+    fixed::SaneFixed,
+};
 use libsane_sys::*;
+use std::convert::{TryFrom, TryInto};
 use std::ffi::CStr;
 use std::num::NonZeroI32;
 
@@ -41,6 +46,29 @@ pub struct Capabilities {
     pub advanced: bool,
 }
 
+impl Capabilities {
+    /// Whether software can set the option's value
+    pub fn is_settable(&self) -> bool {
+        matches!(self.settable, Settable::Software)
+    }
+
+    /// Whether software can read the option's value
+    pub fn is_readable(&self) -> bool {
+        matches!(
+            self.settable,
+            Settable::Software
+                | Settable::Hardware {
+                    software_visible: true
+                }
+        )
+    }
+
+    /// Whether the option currently has an effect, see `inactive`
+    pub fn is_active(&self) -> bool {
+        !self.inactive
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Settable {
     /// The option value can only be set in software
@@ -52,7 +80,7 @@ pub enum Settable {
     },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Unit {
     /// Value is unit-less (e.g., page count).
     None,
@@ -77,7 +105,13 @@ pub enum Constraint<'a> {
         max: i32,
         quant: Option<NonZeroI32>,
     },
+    FixedRange {
+        min: SaneFixed,
+        max: SaneFixed,
+        quant: Option<SaneFixed>,
+    },
     List(&'a [SANE_Word]),
+    FixedList(&'a [SaneFixed]),
     StringList(Vec<&'a CStr>),
 }
 
@@ -99,34 +133,110 @@ fn null_term_cstring_list<'a>(list: *const SANE_String_Const) -> Vec<&'a CStr> {
 }
 
 impl<'a> Constraint<'a> {
-    pub(crate) fn new(
+    /// Read the constraint that `constraint_type` selects from `value_ptr`.
+    ///
+    /// # Safety
+    ///
+    /// The pointer selected by `constraint_type` must be null or point to a valid range,
+    /// null-terminated string list or word list, which lives at least as long as `value_ptr`.
+    pub(crate) unsafe fn new(
         constraint_type: SANE_Constraint_Type,
-        value_ptr: &SANE_Option_Descriptor__bindgen_ty_1,
-    ) -> Self {
-        unsafe {
-            match constraint_type {
-                SANE_Constraint_Type_SANE_CONSTRAINT_NONE => Constraint::None,
-                SANE_Constraint_Type_SANE_CONSTRAINT_RANGE => {
-                    let range = *value_ptr.range;
-                    Constraint::Range {
+        value_ptr: &'a SANE_Option_Descriptor__bindgen_ty_1,
+        value_type: ValueType,
+    ) -> Result<Self> {
+        Ok(match constraint_type {
+            SANE_Constraint_Type_SANE_CONSTRAINT_NONE => Constraint::None,
+            SANE_Constraint_Type_SANE_CONSTRAINT_RANGE => {
+                if value_ptr.range.is_null() {
+                    return Err(SaneError::MalformedConstraint);
+                }
+                let range = *value_ptr.range;
+                let quant = NonZeroI32::new(range.quant);
+                match value_type {
+                    ValueType::Fixed => Constraint::FixedRange {
+                        min: SaneFixed(range.min),
+                        max: SaneFixed(range.max),
+                        quant: quant.map(|q| SaneFixed(q.get())),
+                    },
+                    _ => Constraint::Range {
                         min: range.min,
                         max: range.max,
-                        quant: NonZeroI32::new(range.max),
-                    }
+                        quant,
+                    },
                 }
-                SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST => {
-                    Constraint::StringList(null_term_cstring_list(value_ptr.string_list))
+            }
+            SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST => {
+                if value_ptr.string_list.is_null() {
+                    return Err(SaneError::MalformedConstraint);
+                }
+                Constraint::StringList(null_term_cstring_list(value_ptr.string_list))
+            }
+            SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST => {
+                if value_ptr.word_list.is_null() {
+                    return Err(SaneError::MalformedConstraint);
                 }
-                SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST => {
-                    let length = *value_ptr.word_list.wrapping_offset(0);
-                    let contents = value_ptr.word_list.wrapping_offset(1);
-                    let list = std::slice::from_raw_parts(contents, length as usize);
-                    Constraint::List(list)
+                // The first word holds the number of entries that follow it
+                let length = usize::try_from(*value_ptr.word_list)
+                    .map_err(|_| SaneError::MalformedConstraint)?;
+                let contents = value_ptr.word_list.wrapping_offset(1);
+                match value_type {
+                    // SaneFixed is a transparent wrapper around SANE_Word
+                    ValueType::Fixed => Constraint::FixedList(std::slice::from_raw_parts(
+                        contents as *const SaneFixed,
+                        length,
+                    )),
+                    _ => Constraint::List(std::slice::from_raw_parts(contents, length)),
                 }
-                _ => panic!("Unrecognized constraint type"),
             }
+            _ => return Err(SaneError::UnknownConstraint(constraint_type as i32)),
+        })
+    }
+
+    /// Enumerate the values of a quantized `Range`, in ascending order.
+    /// Returns `None` for other constraints and ranges without quantization.
+    pub fn range_values(&self) -> Option<impl Iterator<Item = SANE_Word>> {
+        match self {
+            Constraint::Range {
+                min,
+                max,
+                quant: Some(quant),
+            } if quant.get() > 0 => Some((*min..=*max).step_by(quant.get() as usize)),
+            _ => None,
         }
     }
+
+    /// Enumerate the values of a quantized `FixedRange`, in ascending order.
+    /// Returns `None` for other constraints and ranges without quantization.
+    pub fn fixed_range_values(&self) -> Option<impl Iterator<Item = SaneFixed>> {
+        match self {
+            Constraint::FixedRange {
+                min,
+                max,
+                quant: Some(quant),
+            } if quant.0 > 0 => Some((min.0..=max.0).step_by(quant.0 as usize).map(SaneFixed)),
+            _ => None,
+        }
+    }
+}
+
+/// Resolutions suggested for ranges that have no quantization
+const COMMON_RESOLUTIONS: &[f64] = &[
+    75.0, 100.0, 150.0, 200.0, 300.0, 400.0, 600.0, 1200.0, 2400.0, 4800.0, 9600.0,
+];
+
+/// The bounds of `min..=max`, along with the `suggestions` that lie in between
+fn range_bounds(min: f64, max: f64, suggestions: &[f64]) -> Vec<f64> {
+    let mut values = vec![min];
+    values.extend(
+        suggestions
+            .iter()
+            .copied()
+            .filter(|&dpi| dpi > min && dpi < max),
+    );
+    if max > min {
+        values.push(max);
+    }
+    values
 }
 
 unsafe fn optional_cstr<'a>(ptr: *const i8) -> Option<&'a CStr> {
@@ -137,10 +247,11 @@ unsafe fn optional_cstr<'a>(ptr: *const i8) -> Option<&'a CStr> {
     }
 }
 
-// TODO: TryFrom?
-impl From<SANE_Unit> for Unit {
-    fn from(unit: SANE_Unit) -> Self {
-        match unit {
+impl TryFrom<SANE_Unit> for Unit {
+    type Error = SaneError;
+
+    fn try_from(unit: SANE_Unit) -> Result<Self> {
+        Ok(match unit {
             SANE_Unit_SANE_UNIT_NONE => Unit::None,
             SANE_Unit_SANE_UNIT_PIXEL => Unit::Pixel,
             SANE_Unit_SANE_UNIT_BIT => Unit::Bit,
@@ -148,14 +259,14 @@ impl From<SANE_Unit> for Unit {
             SANE_Unit_SANE_UNIT_DPI => Unit::DPI,
             SANE_Unit_SANE_UNIT_PERCENT => Unit::Percent,
             SANE_Unit_SANE_UNIT_MICROSECOND => Unit::Microsecond,
-            _ => panic!("Unrecognized or unsupported unit specifier"),
-        }
+            _ => return Err(SaneError::UnknownUnit(unit as i32)),
+        })
     }
 }
 
-impl Into<SANE_Unit> for Unit {
-    fn into(self) -> SANE_Unit {
-        match self {
+impl From<Unit> for SANE_Unit {
+    fn from(unit: Unit) -> Self {
+        match unit {
             Unit::None => SANE_Unit_SANE_UNIT_NONE,
             Unit::Pixel => SANE_Unit_SANE_UNIT_PIXEL,
             Unit::Bit => SANE_Unit_SANE_UNIT_BIT,
@@ -195,36 +306,263 @@ impl From<SANE_Int> for Capabilities {
     }
 }
 
-impl From<SANE_Value_Type> for ValueType {
-    fn from(vt: SANE_Value_Type) -> Self {
-        match vt {
+impl From<Capabilities> for SANE_Int {
+    fn from(capabilities: Capabilities) -> Self {
+        let mut cap = match capabilities.settable {
+            Settable::Software => SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT,
+            Settable::Hardware {
+                software_visible: true,
+            } => SANE_CAP_HARD_SELECT | SANE_CAP_SOFT_DETECT,
+            Settable::Hardware {
+                software_visible: false,
+            } => SANE_CAP_HARD_SELECT,
+        };
+        if capabilities.emulated {
+            cap |= SANE_CAP_EMULATED;
+        }
+        if capabilities.automatic {
+            cap |= SANE_CAP_AUTOMATIC;
+        }
+        if capabilities.inactive {
+            cap |= SANE_CAP_INACTIVE;
+        }
+        if capabilities.advanced {
+            cap |= SANE_CAP_ADVANCED;
+        }
+        cap as SANE_Int
+    }
+}
+
+impl TryFrom<SANE_Value_Type> for ValueType {
+    type Error = SaneError;
+
+    fn try_from(vt: SANE_Value_Type) -> Result<Self> {
+        Ok(match vt {
             SANE_Value_Type_SANE_TYPE_BOOL => ValueType::Bool,
             SANE_Value_Type_SANE_TYPE_BUTTON => ValueType::Button,
             SANE_Value_Type_SANE_TYPE_FIXED => ValueType::Fixed,
             SANE_Value_Type_SANE_TYPE_GROUP => ValueType::Group,
             SANE_Value_Type_SANE_TYPE_INT => ValueType::Int,
             SANE_Value_Type_SANE_TYPE_STRING => ValueType::String,
-            _ => panic!("Invalid value type"),
+            _ => return Err(SaneError::UnknownValueType(vt as i32)),
+        })
+    }
+}
+
+impl From<ValueType> for SANE_Value_Type {
+    fn from(value_type: ValueType) -> Self {
+        match value_type {
+            ValueType::Bool => SANE_Value_Type_SANE_TYPE_BOOL,
+            ValueType::Int => SANE_Value_Type_SANE_TYPE_INT,
+            ValueType::Fixed => SANE_Value_Type_SANE_TYPE_FIXED,
+            ValueType::String => SANE_Value_Type_SANE_TYPE_STRING,
+            ValueType::Button => SANE_Value_Type_SANE_TYPE_BUTTON,
+            ValueType::Group => SANE_Value_Type_SANE_TYPE_GROUP,
         }
     }
 }
 
 impl<'a> OptionDescriptor<'a> {
-    pub(crate) fn from_descriptor(descriptor: &'a SANE_Option_Descriptor, number: SANE_Int) -> Self {
+    pub(crate) fn from_descriptor(
+        descriptor: &'a SANE_Option_Descriptor,
+        number: SANE_Int,
+    ) -> Result<Self> {
+        let value_type = descriptor.type_.try_into()?;
         unsafe {
-            Self {
+            Ok(Self {
                 name: optional_cstr(descriptor.name),
                 title: optional_cstr(descriptor.title),
                 description: optional_cstr(descriptor.desc),
                 capabilities: Capabilities::from(descriptor.cap),
-                constraint: Constraint::new(descriptor.constraint_type, &descriptor.constraint),
-                unit: descriptor.unit.into(),
+                constraint: Constraint::new(
+                    descriptor.constraint_type,
+                    &descriptor.constraint,
+                    value_type,
+                )?,
+                unit: descriptor.unit.try_into()?,
                 size: descriptor.size,
-                value_type: descriptor.type_.into(),
+                value_type,
                 number,
+            })
+        }
+    }
+}
+
+/// Whether `word` lies within `min..=max` and on the quantization grid starting at `min`
+fn in_range(word: SANE_Word, min: SANE_Word, max: SANE_Word, quant: Option<SANE_Word>) -> bool {
+    let on_step = match quant {
+        Some(quant) if quant > 0 => (word as i64 - min as i64) % quant as i64 == 0,
+        _ => true,
+    };
+    word >= min && word <= max && on_step
+}
+
+/// Clamp `word` to `min..=max` and round it to the nearest quantization step
+fn snap_to_range(
+    word: SANE_Word,
+    min: SANE_Word,
+    max: SANE_Word,
+    quant: Option<SANE_Word>,
+) -> SANE_Word {
+    let word = word.max(min).min(max) as i64;
+    match quant {
+        Some(quant) if quant > 0 => {
+            let (min, max, quant) = (min as i64, max as i64, quant as i64);
+            let mut snapped = min + (word - min + quant / 2) / quant * quant;
+            if snapped > max {
+                snapped -= quant;
+            }
+            snapped as SANE_Word
+        }
+        _ => word as SANE_Word,
+    }
+}
+
+/// The entry of `list` closest to `word`
+fn nearest(word: SANE_Word, list: &[SANE_Word]) -> Result<SANE_Word> {
+    list.iter()
+        .copied()
+        .min_by_key(|&entry| (entry as i64 - word as i64).abs())
+        .ok_or(SaneError::ConstraintViolation)
+}
+
+impl OptionDescriptor<'_> {
+    /// Check that `value` has this option's type and size. Word arrays must fill the option
+    /// exactly, while strings may be shorter.
+    fn check_type(&self, value: &Value) -> Result<()> {
+        let word_size = std::mem::size_of::<SANE_Word>();
+        let (length, exact) = match (value, self.value_type) {
+            (Value::Bool(b), ValueType::Bool) => (b.len() * word_size, true),
+            (Value::Int(i), ValueType::Int) => (i.len() * word_size, true),
+            (Value::Fixed(f), ValueType::Fixed) => (f.len() * word_size, true),
+            (Value::String(s), ValueType::String) => (s.to_bytes_with_nul().len(), false),
+            _ => return Err(SaneError::TypeMismatch),
+        };
+
+        let size = self.size as usize;
+        if length == size || (!exact && length < size) {
+            Ok(())
+        } else {
+            Err(SaneError::ConstraintViolation)
+        }
+    }
+
+    /// Allowed resolutions in DPI, in ascending order. Returns `None` if the option's unit is not DPI.
+    ///
+    /// Quantized ranges and lists yield all their values. Ranges without quantization accept any
+    /// value between their bounds, so only the bounds themselves are yielded.
+    pub fn dpi_values(&self) -> Option<impl Iterator<Item = f64>> {
+        self.resolutions(&[])
+    }
+
+    /// Like `dpi_values`, but ranges without quantization also yield common resolutions between
+    /// their bounds. These are suggestions for a resolution picker, not values the backend reported.
+    pub fn suggested_dpi_values(&self) -> Option<impl Iterator<Item = f64>> {
+        self.resolutions(COMMON_RESOLUTIONS)
+    }
+
+    fn resolutions(&self, suggestions: &[f64]) -> Option<std::vec::IntoIter<f64>> {
+        if self.unit != Unit::DPI {
+            return None;
+        }
+
+        let mut values: Vec<f64> = match &self.constraint {
+            Constraint::Range { min, max, .. } => match self.constraint.range_values() {
+                Some(values) => values.map(|dpi| dpi as f64).collect(),
+                None => range_bounds(*min as f64, *max as f64, suggestions),
+            },
+            Constraint::FixedRange { min, max, .. } => match self.constraint.fixed_range_values() {
+                Some(values) => values.map(SaneFixed::to_f64).collect(),
+                None => range_bounds(min.to_f64(), max.to_f64(), suggestions),
+            },
+            Constraint::List(list) => list.iter().map(|&dpi| dpi as f64).collect(),
+            Constraint::FixedList(list) => list.iter().map(|dpi| dpi.to_f64()).collect(),
+            Constraint::None | Constraint::StringList(_) => Vec::new(),
+        };
+        values.sort_by(f64::total_cmp);
+        values.dedup();
+        Some(values.into_iter())
+    }
+
+    /// Check that `value` is a legal value for this option. Inactive options fail with
+    /// `SaneError::Invalid` and options that can only be set in hardware with `SaneError::Unsupported`.
+    pub fn validate(&self, value: &Value) -> Result<()> {
+        if !self.capabilities.is_settable() {
+            return Err(SaneError::Unsupported);
+        }
+        if !self.capabilities.is_active() {
+            return Err(SaneError::Invalid);
+        }
+        self.check_type(value)?;
+
+        let valid = match (value, &self.constraint) {
+            (Value::Int(words), Constraint::Range { min, max, quant }) => words
+                .iter()
+                .all(|&word| in_range(word, *min, *max, quant.map(NonZeroI32::get))),
+            (Value::Fixed(words), Constraint::FixedRange { min, max, quant }) => words
+                .iter()
+                .all(|word| in_range(word.0, min.0, max.0, quant.map(|q| q.0))),
+            (Value::Int(words), Constraint::List(list)) => {
+                words.iter().all(|word| list.contains(word))
+            }
+            (Value::Fixed(words), Constraint::FixedList(list)) => {
+                words.iter().all(|word| list.contains(word))
+            }
+            (Value::String(string), Constraint::StringList(list)) => {
+                list.iter().any(|entry| **entry == **string)
             }
+            _ => true,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(SaneError::ConstraintViolation)
         }
     }
+
+    /// Turn `value` into the closest legal value for this option. Numbers are clamped to a range
+    /// and rounded to its quantization, or replaced by the nearest list entry. Strings are matched
+    /// case-insensitively against a string list.
+    pub fn snap(&self, value: &Value) -> Result<Value> {
+        self.check_type(value)?;
+
+        Ok(match (value, &self.constraint) {
+            (Value::Int(words), Constraint::Range { min, max, quant }) => Value::Int(
+                words
+                    .iter()
+                    .map(|&word| snap_to_range(word, *min, *max, quant.map(NonZeroI32::get)))
+                    .collect(),
+            ),
+            (Value::Fixed(words), Constraint::FixedRange { min, max, quant }) => Value::Fixed(
+                words
+                    .iter()
+                    .map(|word| SaneFixed(snap_to_range(word.0, min.0, max.0, quant.map(|q| q.0))))
+                    .collect(),
+            ),
+            (Value::Int(words), Constraint::List(list)) => Value::Int(
+                words
+                    .iter()
+                    .map(|&word| nearest(word, list))
+                    .collect::<Result<_>>()?,
+            ),
+            (Value::Fixed(words), Constraint::FixedList(list)) => {
+                let list: Vec<SANE_Word> = list.iter().map(|entry| entry.0).collect();
+                Value::Fixed(
+                    words
+                        .iter()
+                        .map(|word| nearest(word.0, &list).map(SaneFixed))
+                        .collect::<Result<_>>()?,
+                )
+            }
+            (Value::String(string), Constraint::StringList(list)) => list
+                .iter()
+                .find(|entry| entry.to_bytes().eq_ignore_ascii_case(string.to_bytes()))
+                .map(|&entry| Value::String(Box::from(entry)))
+                .ok_or(SaneError::ConstraintViolation)?,
+            _ => value.clone(),
+        })
+    }
 }
 
 pub struct OptionDescriptorIterator<'device, 'sane> {
@@ -234,41 +572,475 @@ pub struct OptionDescriptorIterator<'device, 'sane> {
 }
 
 impl<'device, 'sane> OptionDescriptorIterator<'device, 'sane> {
-    pub(crate) fn new(device: &'device Device<'sane>) -> Self {
-        let mut length: SANE_Int = 0;
-        // TODO: Clean up this getter (Abstract elsewhere eventually)
-        unsafe {
-            let mut info: SANE_Int = 0;
-            SaneError::from_retcode(sane_control_option(
-                device.get_handle(),
-                0,
-                SANE_Action_SANE_ACTION_GET_VALUE,
-                &mut length as *mut i32 as *mut std::ffi::c_void,
-                &mut info as *mut i32,
-            ))
-            .unwrap()
-        }
-
-        Self {
+    pub(crate) fn new(device: &'device Device<'sane>) -> Result<Self> {
+        Ok(Self {
             device,
-            length,
+            length: device.option_count()?,
             position: 0,
-        }
+        })
     }
 }
 
 impl<'device, 'sane> Iterator for OptionDescriptorIterator<'device, 'sane> {
-    type Item = OptionDescriptor<'device>;
+    type Item = Result<OptionDescriptor<'device>>;
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            let ptr = sane_get_option_descriptor(self.device.get_handle(), self.position);
-            if self.position >= self.length || ptr.is_null() {
-                None
-            } else {
-                let desc = OptionDescriptor::from_descriptor(&*ptr, self.position);
-                self.position += 1;
-                Some(desc)
+        if self.position >= self.length {
+            return None;
+        }
+        let descriptor = self.device.raw_descriptor(self.position)?;
+        let desc = OptionDescriptor::from_descriptor(descriptor, self.position);
+        self.position += 1;
+        Some(desc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(value_type: ValueType, constraint: Constraint) -> OptionDescriptor {
+        OptionDescriptor {
+            number: 1,
+            name: None,
+            title: None,
+            description: None,
+            value_type,
+            capabilities: Capabilities::from(
+                (SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT) as SANE_Int,
+            ),
+            unit: Unit::None,
+            size: std::mem::size_of::<SANE_Word>() as SANE_Int,
+            constraint,
+        }
+    }
+
+    fn int(word: SANE_Word) -> Value {
+        Value::Int(Box::new([word]))
+    }
+
+    fn fixed(value: f64) -> Value {
+        Value::Fixed(Box::new([SaneFixed::from_f64(value)]))
+    }
+
+    fn cstr(bytes: &[u8]) -> &CStr {
+        CStr::from_bytes_with_nul(bytes).unwrap()
+    }
+
+    fn string(value: &CStr) -> Value {
+        Value::String(Box::from(value))
+    }
+
+    fn range(min: i32, max: i32, quant: i32) -> Constraint<'static> {
+        Constraint::Range {
+            min,
+            max,
+            quant: NonZeroI32::new(quant),
+        }
+    }
+
+    fn constraint(
+        constraint_type: SANE_Constraint_Type,
+        value: &SANE_Option_Descriptor__bindgen_ty_1,
+    ) -> Result<Constraint<'_>> {
+        // The tests keep the constraint data alive for as long as `value`
+        unsafe { Constraint::new(constraint_type, value, ValueType::Int) }
+    }
+
+    fn snapped_int(descriptor: &OptionDescriptor, word: SANE_Word) -> SANE_Word {
+        match descriptor.snap(&int(word)).unwrap() {
+            Value::Int(words) => words[0],
+            value => panic!("unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn validates_range_bounds() {
+        let option = descriptor(ValueType::Int, range(10, 20, 0));
+        assert!(option.validate(&int(10)).is_ok());
+        assert!(option.validate(&int(20)).is_ok());
+        assert!(matches!(
+            option.validate(&int(9)),
+            Err(SaneError::ConstraintViolation)
+        ));
+        assert!(matches!(
+            option.validate(&int(21)),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn validates_quantization_from_min() {
+        let option = descriptor(ValueType::Int, range(5, 25, 10));
+        assert!(option.validate(&int(15)).is_ok());
+        assert!(matches!(
+            option.validate(&int(10)),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn snaps_to_bounds_and_steps() {
+        let option = descriptor(ValueType::Int, range(0, 100, 25));
+        assert_eq!(snapped_int(&option, -5), 0);
+        assert_eq!(snapped_int(&option, 130), 100);
+        assert_eq!(snapped_int(&option, 37), 25);
+        assert_eq!(snapped_int(&option, 38), 50);
+    }
+
+    #[test]
+    fn snaps_below_max_when_quant_does_not_divide_range() {
+        // The steps are 0, 30, 60 and 90, so 100 itself is not reachable
+        let option = descriptor(ValueType::Int, range(0, 100, 30));
+        assert_eq!(snapped_int(&option, 100), 90);
+        assert!(matches!(
+            option.validate(&int(100)),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn snaps_word_list_to_nearest_entry() {
+        let list = [75, 150, 300];
+        let option = descriptor(ValueType::Int, Constraint::List(&list));
+        assert!(option.validate(&int(150)).is_ok());
+        assert!(matches!(
+            option.validate(&int(200)),
+            Err(SaneError::ConstraintViolation)
+        ));
+        assert_eq!(snapped_int(&option, 200), 150);
+        assert_eq!(snapped_int(&option, 1000), 300);
+    }
+
+    #[test]
+    fn snaps_empty_word_list_to_error() {
+        let option = descriptor(ValueType::Int, Constraint::List(&[]));
+        assert!(matches!(
+            option.snap(&int(1)),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn matches_string_list_case_insensitively() {
+        let list = vec![cstr(b"Color\0"), cstr(b"Gray\0")];
+        let mut option = descriptor(ValueType::String, Constraint::StringList(list));
+        option.size = 16;
+        assert!(option.validate(&string(cstr(b"Gray\0"))).is_ok());
+        assert!(matches!(
+            option.validate(&string(cstr(b"gray\0"))),
+            Err(SaneError::ConstraintViolation)
+        ));
+        assert!(
+            matches!(option.snap(&string(cstr(b"gray\0"))), Ok(Value::String(s)) if *s == *cstr(b"Gray\0"))
+        );
+        assert!(matches!(
+            option.snap(&string(cstr(b"Lineart\0"))),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn rejects_strings_longer_than_the_option() {
+        let option = descriptor(ValueType::String, Constraint::None);
+        assert!(matches!(
+            option.validate(&string(cstr(b"Color\0"))),
+            Err(SaneError::ConstraintViolation)
+        ));
+    }
+
+    #[test]
+    fn requires_word_arrays_to_fill_the_option() {
+        let mut option = descriptor(ValueType::Int, Constraint::None);
+        option.size = 3 * std::mem::size_of::<SANE_Word>() as SANE_Int;
+        assert!(option.validate(&Value::Int(Box::new([1, 2, 3]))).is_ok());
+        for words in &[&[1, 2][..], &[1, 2, 3, 4], &[]] {
+            assert!(matches!(
+                option.validate(&Value::Int(Box::from(*words))),
+                Err(SaneError::ConstraintViolation)
+            ));
+        }
+
+        let mut option = descriptor(ValueType::Bool, Constraint::None);
+        option.size *= 2;
+        assert!(matches!(
+            option.snap(&Value::Bool(Box::new([true]))),
+            Err(SaneError::ConstraintViolation)
+        ));
+
+        let mut option = descriptor(ValueType::String, Constraint::None);
+        option.size = 32;
+        assert!(option.validate(&string(cstr(b"Color\0"))).is_ok());
+    }
+
+    #[test]
+    fn validates_only_settable_active_options() {
+        let mut count = descriptor(ValueType::Int, Constraint::None);
+        count.number = 0;
+        count.capabilities = Capabilities::from(SANE_CAP_SOFT_DETECT as SANE_Int);
+        let mut switch = descriptor(ValueType::Bool, Constraint::None);
+        switch.capabilities =
+            Capabilities::from((SANE_CAP_HARD_SELECT | SANE_CAP_SOFT_DETECT) as SANE_Int);
+        let mut inactive = descriptor(ValueType::Fixed, Constraint::None);
+        inactive.capabilities.inactive = true;
+        let resolution = descriptor(ValueType::Int, range(75, 1200, 0));
+        let mut mode = descriptor(
+            ValueType::String,
+            Constraint::StringList(vec![cstr(b"Gray\0")]),
+        );
+        mode.size = 8;
+        let preview = descriptor(ValueType::Bool, Constraint::None);
+
+        let options = vec![
+            (count, int(6), Err(SaneError::Unsupported)),
+            (
+                switch,
+                Value::Bool(Box::new([true])),
+                Err(SaneError::Unsupported),
+            ),
+            (inactive, fixed(1.0), Err(SaneError::Invalid)),
+            (resolution, int(300), Ok(())),
+            (mode, string(cstr(b"Gray\0")), Ok(())),
+            (preview, Value::Bool(Box::new([false])), Ok(())),
+        ];
+        for (option, value, expected) in options {
+            let settable = option.capabilities.is_settable() && option.capabilities.is_active();
+            assert_eq!(settable, expected.is_ok(), "{:?}", option);
+            match (option.validate(&value), expected) {
+                (Ok(()), Ok(())) => {}
+                (Err(SaneError::Unsupported), Err(SaneError::Unsupported)) => {}
+                (Err(SaneError::Invalid), Err(SaneError::Invalid)) => {}
+                (result, _) => panic!("unexpected result {:?} for {:?}", result, option),
             }
         }
     }
+
+    #[test]
+    fn distinguishes_fixed_from_int() {
+        let option = descriptor(
+            ValueType::Fixed,
+            Constraint::FixedRange {
+                min: SaneFixed::from_f64(0.0),
+                max: SaneFixed::from_f64(1.0),
+                quant: Some(SaneFixed::from_f64(0.25)),
+            },
+        );
+        assert!(option.validate(&fixed(0.5)).is_ok());
+        assert!(matches!(
+            option.validate(&int(0)),
+            Err(SaneError::TypeMismatch)
+        ));
+        assert!(matches!(option.snap(&fixed(0.3)), Ok(Value::Fixed(f)) if f[0].to_f64() == 0.25));
+
+        let option = descriptor(ValueType::Int, range(0, 1, 0));
+        assert!(matches!(
+            option.validate(&fixed(0.5)),
+            Err(SaneError::TypeMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_inactive_options() {
+        let mut option = descriptor(ValueType::Int, Constraint::None);
+        option.capabilities.inactive = true;
+        assert!(matches!(option.validate(&int(1)), Err(SaneError::Invalid)));
+    }
+
+    #[test]
+    fn rejects_read_only_options() {
+        let mut option = descriptor(ValueType::Int, Constraint::None);
+        option.capabilities.settable = Settable::Hardware {
+            software_visible: true,
+        };
+        assert!(matches!(
+            option.validate(&int(1)),
+            Err(SaneError::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn reads_quantization_from_the_range() {
+        let range = SANE_Range {
+            min: 50,
+            max: 600,
+            quant: 25,
+        };
+        let value = SANE_Option_Descriptor__bindgen_ty_1 { range: &range };
+        let constraint = constraint(SANE_Constraint_Type_SANE_CONSTRAINT_RANGE, &value).unwrap();
+        assert!(matches!(
+            constraint,
+            Constraint::Range { min: 50, max: 600, quant: Some(q) } if q.get() == 25
+        ));
+    }
+
+    #[test]
+    fn enumerates_steps_that_do_not_divide_the_range() {
+        let values: Vec<_> = range(0, 100, 30).range_values().unwrap().collect();
+        assert_eq!(values, [0, 30, 60, 90]);
+
+        let constraint = Constraint::FixedRange {
+            min: SaneFixed::from_f64(1.0),
+            max: SaneFixed::from_f64(2.0),
+            quant: Some(SaneFixed::from_f64(0.375)),
+        };
+        let values: Vec<_> = constraint
+            .fixed_range_values()
+            .unwrap()
+            .map(SaneFixed::to_f64)
+            .collect();
+        assert_eq!(values, [1.0, 1.375, 1.75]);
+    }
+
+    #[test]
+    fn does_not_enumerate_unquantized_ranges() {
+        assert!(range(0, 100, 0).range_values().is_none());
+        let constraint = Constraint::FixedRange {
+            min: SaneFixed::from_f64(0.0),
+            max: SaneFixed::from_f64(1.0),
+            quant: Some(SaneFixed(0)),
+        };
+        assert!(constraint.fixed_range_values().is_none());
+    }
+
+    #[test]
+    fn enumerates_single_value_ranges() {
+        let values: Vec<_> = range(300, 300, 50).range_values().unwrap().collect();
+        assert_eq!(values, [300]);
+        assert!(range(300, 300, 0).range_values().is_none());
+
+        let constraint = Constraint::FixedRange {
+            min: SaneFixed::from_f64(2.5),
+            max: SaneFixed::from_f64(2.5),
+            quant: Some(SaneFixed::from_f64(1.0)),
+        };
+        let values: Vec<_> = constraint.fixed_range_values().unwrap().collect();
+        assert_eq!(values, [SaneFixed::from_f64(2.5)]);
+    }
+
+    #[test]
+    fn enumerates_ranges_ending_at_the_largest_word() {
+        let values: Vec<_> = range(i32::MAX - 10, i32::MAX, 4)
+            .range_values()
+            .unwrap()
+            .collect();
+        assert_eq!(values, [i32::MAX - 10, i32::MAX - 6, i32::MAX - 2]);
+
+        let values: Vec<_> = range(i32::MAX - 2, i32::MAX, 1)
+            .range_values()
+            .unwrap()
+            .collect();
+        assert_eq!(values, [i32::MAX - 2, i32::MAX - 1, i32::MAX]);
+
+        let values: Vec<_> = range(i32::MIN, i32::MAX, i32::MAX)
+            .range_values()
+            .unwrap()
+            .collect();
+        assert_eq!(values, [i32::MIN, -1, i32::MAX - 1]);
+
+        let constraint = Constraint::FixedRange {
+            min: SaneFixed(i32::MAX - 1),
+            max: SaneFixed(i32::MAX),
+            quant: Some(SaneFixed(1)),
+        };
+        let values: Vec<_> = constraint.fixed_range_values().unwrap().collect();
+        assert_eq!(values, [SaneFixed(i32::MAX - 1), SaneFixed(i32::MAX)]);
+    }
+
+    #[test]
+    fn does_not_enumerate_negative_quantization() {
+        assert!(range(0, 100, -10).range_values().is_none());
+        let constraint = Constraint::FixedRange {
+            min: SaneFixed::from_f64(0.0),
+            max: SaneFixed::from_f64(1.0),
+            quant: Some(SaneFixed::from_f64(-0.25)),
+        };
+        assert!(constraint.fixed_range_values().is_none());
+    }
+
+    #[test]
+    fn enumerates_nothing_for_inverted_ranges() {
+        assert_eq!(range(10, 0, 1).range_values().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn does_not_round_unquantized_ranges() {
+        let option = descriptor(ValueType::Int, range(0, 100, 0));
+        assert!(option.validate(&int(37)).is_ok());
+        assert_eq!(snapped_int(&option, 37), 37);
+        assert_eq!(snapped_int(&option, 101), 100);
+    }
+
+    #[test]
+    fn lists_dpi_values_of_quantized_ranges() {
+        let mut option = descriptor(ValueType::Int, range(100, 400, 150));
+        option.unit = Unit::DPI;
+        assert_eq!(
+            option.dpi_values().unwrap().collect::<Vec<_>>(),
+            [100.0, 250.0, 400.0]
+        );
+    }
+
+    #[test]
+    fn only_suggests_dpi_values_for_unquantized_ranges() {
+        let mut option = descriptor(ValueType::Int, range(50, 600, 0));
+        option.unit = Unit::DPI;
+        assert_eq!(
+            option.dpi_values().unwrap().collect::<Vec<_>>(),
+            [50.0, 600.0]
+        );
+        assert_eq!(
+            option.suggested_dpi_values().unwrap().collect::<Vec<_>>(),
+            [50.0, 75.0, 100.0, 150.0, 200.0, 300.0, 400.0, 600.0]
+        );
+    }
+
+    #[test]
+    fn merges_dpi_lists() {
+        let list = [300, 75, 150, 75];
+        let mut option = descriptor(ValueType::Int, Constraint::List(&list));
+        option.unit = Unit::DPI;
+        assert_eq!(
+            option.dpi_values().unwrap().collect::<Vec<_>>(),
+            [75.0, 150.0, 300.0]
+        );
+
+        option.unit = Unit::None;
+        assert!(option.dpi_values().is_none());
+    }
+
+    #[test]
+    fn reads_word_lists() {
+        let words = [3, 75, 150, 300];
+        let value = SANE_Option_Descriptor__bindgen_ty_1 {
+            word_list: words.as_ptr(),
+        };
+        let constraint = constraint(SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST, &value);
+        assert!(matches!(constraint, Ok(Constraint::List(list)) if list == [75, 150, 300]));
+    }
+
+    #[test]
+    fn rejects_negative_word_list_length() {
+        let words = [-1, 75];
+        let value = SANE_Option_Descriptor__bindgen_ty_1 {
+            word_list: words.as_ptr(),
+        };
+        let constraint = constraint(SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST, &value);
+        assert!(matches!(constraint, Err(SaneError::MalformedConstraint)));
+    }
+
+    #[test]
+    fn rejects_null_constraints() {
+        let null = SANE_Option_Descriptor__bindgen_ty_1 {
+            range: std::ptr::null(),
+        };
+        for constraint_type in [
+            SANE_Constraint_Type_SANE_CONSTRAINT_RANGE,
+            SANE_Constraint_Type_SANE_CONSTRAINT_WORD_LIST,
+            SANE_Constraint_Type_SANE_CONSTRAINT_STRING_LIST,
+        ] {
+            assert!(matches!(
+                constraint(constraint_type, &null),
+                Err(SaneError::MalformedConstraint)
+            ));
+        }
+    }
 }
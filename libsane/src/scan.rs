@@ -0,0 +1,167 @@
+#[cfg(feature = "async")]
+use crate::async_scan::AsyncFrame;
+use crate::device::{Device, ScanParameters};
+use crate::error::{Result, SaneError};
+use libsane_sys::*;
+use std::{io, marker::PhantomData};
+
+/// A running acquisition on a `Device`, started by `Device::start`.
+///
+/// The scan is cancelled with `sane_cancel` when this session is dropped. It borrows the device
+/// mutably, as SANE allows only one acquisition per handle.
+pub struct Scan<'device, 'sane> {
+    device: &'device mut Device<'sane>,
+    non_blocking: bool,
+}
+
+/// File descriptor that becomes readable when image data is available, see `Scan::select_fd`.
+///
+/// Only valid while the scan it was obtained from is running.
+#[derive(Debug)]
+pub struct SelectFd<'scan> {
+    fd: SANE_Int,
+    _phantomdata: PhantomData<&'scan ()>,
+}
+
+impl SelectFd<'_> {
+    /// The raw file descriptor number.
+    pub fn fd(&self) -> SANE_Int {
+        self.fd
+    }
+}
+
+#[cfg(unix)]
+impl std::os::unix::io::AsRawFd for SelectFd<'_> {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.fd
+    }
+}
+
+impl<'device, 'sane> Scan<'device, 'sane> {
+    pub(crate) fn start(device: &'device mut Device<'sane>) -> Result<Self> {
+        unsafe {
+            SaneError::from_retcode(sane_start(device.get_handle()))?;
+        }
+        Ok(Self {
+            device,
+            non_blocking: false,
+        })
+    }
+
+    pub(crate) fn device(&self) -> &Device<'sane> {
+        self.device
+    }
+
+    /// Parameters of the frame currently being acquired.
+    pub fn parameters(&self) -> Result<ScanParameters> {
+        self.device.get_params()
+    }
+
+    /// Read image data of the current frame into `buf`.
+    /// Returns the number of bytes read, or `None` once the frame is complete.
+    /// In non-blocking mode, `Some(0)` means that no data is available yet.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let max_length = buf.len().min(SANE_Int::MAX as usize) as SANE_Int;
+        let mut length: SANE_Int = 0;
+        let status = unsafe {
+            sane_read(
+                self.device.get_handle(),
+                buf.as_mut_ptr(),
+                max_length,
+                &mut length as *mut SANE_Int,
+            )
+        };
+
+        match SaneError::from_retcode(status) {
+            Ok(()) => Ok(Some(length as usize)),
+            Err(SaneError::EOF) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Start acquiring the next frame of a multi frame image, once the
+    /// current frame is complete and it was not the last one.
+    /// The I/O mode has to be set again for the new frame.
+    pub fn next_frame(&mut self) -> Result<()> {
+        unsafe { SaneError::from_retcode(sane_start(self.device.get_handle()))? };
+        self.non_blocking = false;
+        Ok(())
+    }
+
+    /// Switch between blocking and non-blocking reads.
+    /// Returns `false` if the backend does not support non-blocking I/O, in which case reads stay blocking.
+    pub fn set_non_blocking(&mut self, non_blocking: bool) -> Result<bool> {
+        let status = unsafe {
+            sane_set_io_mode(
+                self.device.get_handle(),
+                if non_blocking { SANE_TRUE } else { SANE_FALSE } as SANE_Bool,
+            )
+        };
+
+        match SaneError::from_retcode(status) {
+            Ok(()) => {
+                self.non_blocking = non_blocking;
+                Ok(true)
+            }
+            Err(SaneError::Unsupported) if non_blocking => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether reads are currently non-blocking.
+    pub fn is_non_blocking(&self) -> bool {
+        self.non_blocking
+    }
+
+    /// A file descriptor to wait on with select/poll/epoll before reading.
+    /// Returns `None` if the backend does not provide one.
+    pub fn select_fd(&self) -> Result<Option<SelectFd<'_>>> {
+        let mut fd: SANE_Int = -1;
+        let status =
+            unsafe { sane_get_select_fd(self.device.get_handle(), &mut fd as *mut SANE_Int) };
+
+        match SaneError::from_retcode(status) {
+            Ok(()) => Ok(Some(SelectFd {
+                fd,
+                _phantomdata: PhantomData,
+            })),
+            Err(SaneError::Unsupported) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the current frame asynchronously.
+    #[cfg(feature = "async")]
+    pub fn frame_async(&mut self) -> Result<AsyncFrame<'_, 'device, 'sane>> {
+        AsyncFrame::new(self)
+    }
+
+    /// Cancel the scan. Equivalent to dropping the session.
+    pub fn cancel(self) {}
+}
+
+impl Drop for Scan<'_, '_> {
+    fn drop(&mut self) {
+        unsafe {
+            sane_cancel(self.device.get_handle());
+        }
+    }
+}
+
+impl io::Read for Scan<'_, '_> {
+    /// Read image data of the current frame. The end of the frame is reported as a zero-length read,
+    /// and a read that would block in non-blocking mode as `io::ErrorKind::WouldBlock`.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match Scan::read(self, buf)? {
+                Some(0) if !buf.is_empty() && self.non_blocking => {
+                    return Err(io::ErrorKind::WouldBlock.into())
+                }
+                // Backends may return no data without reaching the end of the frame
+                Some(0) if !buf.is_empty() => continue,
+                Some(length) => return Ok(length),
+                None => return Ok(0),
+            }
+        }
+    }
+}
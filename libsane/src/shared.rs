@@ -0,0 +1,39 @@
+use crate::{device::Device, error::Result, LibSane, SaneError};
+use std::{
+    ffi::CString,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A device that can be shared between threads.
+///
+/// Clones refer to the same device, which is closed when the last clone is dropped. Calls on the
+/// device are serialized through `lock`.
+#[derive(Clone)]
+pub struct SharedDevice {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // Declared before `_sane`, so the device is closed before the library may exit
+    device: Mutex<Device<'static>>,
+    _sane: Arc<LibSane>,
+}
+
+impl SharedDevice {
+    pub(crate) fn open(sane: Arc<LibSane>, name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(|_| SaneError::Invalid)?;
+        // The 'static lifetime stands in for the Arc, which keeps the library alive just as well
+        let device = Device::open_device(&name)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                device: Mutex::new(device),
+                _sane: sane,
+            }),
+        })
+    }
+
+    /// Get exclusive access to the device, blocking while another thread uses it.
+    pub fn lock(&self) -> MutexGuard<'_, Device<'static>> {
+        self.inner.device.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,36 @@
+use libsane_sys::*;
+use std::fmt;
+
+/// Version of the SANE library, as reported by `sane_init`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SaneVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u16,
+}
+
+impl From<SANE_Int> for SaneVersion {
+    /// Decode a version code like the `SANE_VERSION_MAJOR/MINOR/BUILD` macros.
+    fn from(code: SANE_Int) -> Self {
+        Self {
+            major: (code >> 24 & 0xff) as u8,
+            minor: (code >> 16 & 0xff) as u8,
+            build: (code & 0xffff) as u16,
+        }
+    }
+}
+
+impl From<SaneVersion> for SANE_Int {
+    /// Encode a version code like the `SANE_VERSION_CODE` macro.
+    fn from(version: SaneVersion) -> Self {
+        (version.major as SANE_Int) << 24
+            | (version.minor as SANE_Int) << 16
+            | version.build as SANE_Int
+    }
+}
+
+impl fmt::Display for SaneVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
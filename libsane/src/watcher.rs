@@ -0,0 +1,311 @@
+use crate::{device_list::DeviceInfo, error::Result, LibSane};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// A change in the set of available devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(DeviceInfo),
+    Removed(DeviceInfo),
+}
+
+/// Detects devices being plugged in or removed by listing the devices periodically.
+#[derive(Debug, Clone)]
+pub struct DeviceWatcher {
+    interval: Duration,
+    local_only: bool,
+    known: HashMap<String, DeviceInfo>,
+}
+
+impl DeviceWatcher {
+    /// Create a watcher that lists the devices every `interval` once spawned.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            local_only: false,
+            known: HashMap::new(),
+        }
+    }
+
+    /// Only watch local devices, skipping the slow search for network scanners.
+    pub fn local_only(mut self, local_only: bool) -> Self {
+        self.local_only = local_only;
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// List the devices once, returning the changes since the previous poll.
+    /// The first poll reports every device as added.
+    pub fn poll(&mut self, sane: &LibSane) -> Result<Vec<DeviceEvent>> {
+        let current: HashMap<String, DeviceInfo> = sane
+            .devices(self.local_only)?
+            .into_iter()
+            .map(|device| (device.name.clone(), device))
+            .collect();
+        let previous = std::mem::replace(&mut self.known, current);
+        Ok(changes(&previous, &self.known))
+    }
+
+    /// Poll on a background thread, passing each event or polling error to `callback`.
+    pub fn spawn<F>(self, sane: Arc<LibSane>, mut callback: F) -> WatcherHandle
+    where
+        F: FnMut(Result<DeviceEvent>) + Send + 'static,
+    {
+        self.spawn_with(sane, move |event| {
+            callback(event);
+            true
+        })
+    }
+
+    /// Poll on a background thread, sending each event or polling error over a channel.
+    /// The thread stops once the receiver is dropped.
+    pub fn spawn_channel(
+        self,
+        sane: Arc<LibSane>,
+    ) -> (WatcherHandle, Receiver<Result<DeviceEvent>>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = self.spawn_with(sane, move |event| sender.send(event).is_ok());
+        (handle, receiver)
+    }
+
+    /// `deliver` returns false once nobody listens anymore
+    fn spawn_with<F>(mut self, sane: Arc<LibSane>, mut deliver: F) -> WatcherHandle
+    where
+        F: FnMut(Result<DeviceEvent>) -> bool + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            let delivered = match self.poll(&sane) {
+                Ok(events) => events.into_iter().all(|event| deliver(Ok(event))),
+                Err(error) => deliver(Err(error)),
+            };
+            if !delivered {
+                return;
+            }
+
+            match receiver.recv_timeout(self.interval) {
+                Ok(Command::Rescan) | Err(RecvTimeoutError::Timeout) => (),
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        WatcherHandle {
+            commands,
+            thread: Some(thread),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// The events that turn the devices in `previous` into those in `current`, both keyed by name.
+/// Removals come before additions, each ordered by name.
+fn changes(
+    previous: &HashMap<String, DeviceInfo>,
+    current: &HashMap<String, DeviceInfo>,
+) -> Vec<DeviceEvent> {
+    let mut removed: Vec<&DeviceInfo> = previous
+        .iter()
+        .filter(|(name, _)| !current.contains_key(*name))
+        .map(|(_, device)| device)
+        .collect();
+    let mut added: Vec<&DeviceInfo> = current
+        .iter()
+        .filter(|(name, _)| !previous.contains_key(*name))
+        .map(|(_, device)| device)
+        .collect();
+    removed.sort_by(|a, b| a.name.cmp(&b.name));
+    added.sort_by(|a, b| a.name.cmp(&b.name));
+
+    removed
+        .into_iter()
+        .map(|device| DeviceEvent::Removed(device.clone()))
+        .chain(
+            added
+                .into_iter()
+                .map(|device| DeviceEvent::Added(device.clone())),
+        )
+        .collect()
+}
+
+enum Command {
+    Rescan,
+    Stop,
+}
+
+/// Controls a spawned `DeviceWatcher`. Dropping the handle stops the watcher.
+pub struct WatcherHandle {
+    commands: Sender<Command>,
+    thread: Option<JoinHandle<()>>,
+    /// Tells helper threads, which only ever send commands, that the watcher is gone
+    stopped: Arc<AtomicBool>,
+}
+
+impl WatcherHandle {
+    /// List the devices now instead of waiting for the interval to pass, e.g. because the system
+    /// reported a hotplug event.
+    pub fn rescan(&self) {
+        let _ = self.commands.send(Command::Rescan);
+    }
+
+    /// Stop the watcher, waiting for a poll in progress to finish.
+    pub fn stop(self) {}
+
+    /// Rescan whenever udev reports a USB device being added or removed, until the watcher stops.
+    #[cfg(feature = "udev")]
+    pub fn rescan_on_udev(&self) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let commands = self.commands.clone();
+        let stopped = self.stopped.clone();
+        // The monitor can't be sent to another thread, so it is set up there and reports back
+        let (setup, setup_result) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let socket = match udev::MonitorBuilder::new()
+                .and_then(|builder| builder.match_subsystem_devtype("usb", "usb_device"))
+                .and_then(|builder| builder.listen())
+            {
+                Ok(socket) => {
+                    let _ = setup.send(Ok(()));
+                    socket
+                }
+                Err(error) => {
+                    let _ = setup.send(Err(error));
+                    return;
+                }
+            };
+
+            loop {
+                let mut fd = libc::pollfd {
+                    fd: socket.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                // Wake up now and then to notice the watcher stopping
+                let ready = unsafe { libc::poll(&mut fd, 1, 1000) };
+                if ready < 0
+                    && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+                {
+                    return;
+                }
+
+                if stopped.load(Ordering::Acquire) {
+                    return;
+                }
+
+                // Drain all pending events, so one burst of them only causes one rescan
+                let changes = socket
+                    .iter()
+                    .filter(|event| {
+                        matches!(
+                            event.event_type(),
+                            udev::EventType::Add | udev::EventType::Remove
+                        )
+                    })
+                    .count();
+                if changes > 0 && commands.send(Command::Rescan).is_err() {
+                    return;
+                }
+            }
+        });
+        setup_result
+            .recv()
+            .unwrap_or_else(|_| Err(std::io::ErrorKind::Other.into()))
+    }
+}
+
+impl Drop for WatcherHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        let _ = self.commands.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_owned(),
+            vendor: "Plustek".to_owned(),
+            model: "OpticPro".to_owned(),
+            type_: "flatbed scanner".to_owned(),
+        }
+    }
+
+    fn devices(names: &[&str]) -> HashMap<String, DeviceInfo> {
+        names
+            .iter()
+            .map(|&name| (name.to_owned(), device(name)))
+            .collect()
+    }
+
+    #[test]
+    fn reports_added_devices() {
+        assert_eq!(
+            changes(&devices(&[]), &devices(&["test:1", "test:0"])),
+            [
+                DeviceEvent::Added(device("test:0")),
+                DeviceEvent::Added(device("test:1")),
+            ]
+        );
+        assert_eq!(
+            changes(
+                &devices(&["test:0"]),
+                &devices(&["test:0", "net:host:test:0"])
+            ),
+            [DeviceEvent::Added(device("net:host:test:0"))]
+        );
+    }
+
+    #[test]
+    fn reports_removed_devices() {
+        assert_eq!(
+            changes(&devices(&["test:0", "test:1"]), &devices(&["test:1"])),
+            [DeviceEvent::Removed(device("test:0"))]
+        );
+        assert_eq!(
+            changes(&devices(&["test:0"]), &devices(&[])),
+            [DeviceEvent::Removed(device("test:0"))]
+        );
+    }
+
+    #[test]
+    fn reports_nothing_for_unchanged_devices() {
+        assert!(changes(&devices(&[]), &devices(&[])).is_empty());
+        assert!(changes(
+            &devices(&["test:0", "test:1"]),
+            &devices(&["test:1", "test:0"])
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn reports_renamed_devices_as_removed_and_added() {
+        // Plugging a USB scanner into another port changes its name
+        assert_eq!(
+            changes(
+                &devices(&["plustek:libusb:001:006"]),
+                &devices(&["plustek:libusb:001:007"])
+            ),
+            [
+                DeviceEvent::Removed(device("plustek:libusb:001:006")),
+                DeviceEvent::Added(device("plustek:libusb:001:007")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! Typed access to the well-known options defined by the SANE standard.
+
+use crate::device::{Device, OptionInfo, Value};
+use crate::error::{Result, SaneError};
+use crate::fixed::SaneFixed;
+use crate::option_descriptor::{OptionDescriptor, Unit, ValueType};
+use std::ffi::CString;
+
+/// Scan in preview mode.
+pub const PREVIEW: &str = "preview";
+/// Number of bits per sample.
+pub const DEPTH: &str = "depth";
+/// Scan resolution.
+pub const RESOLUTION: &str = "resolution";
+/// Scan resolution in horizontal direction.
+pub const X_RESOLUTION: &str = "x-resolution";
+/// Scan resolution in vertical direction.
+pub const Y_RESOLUTION: &str = "y-resolution";
+/// Top-left x position of the scan area.
+pub const TL_X: &str = "tl-x";
+/// Top-left y position of the scan area.
+pub const TL_Y: &str = "tl-y";
+/// Bottom-right x position of the scan area.
+pub const BR_X: &str = "br-x";
+/// Bottom-right y position of the scan area.
+pub const BR_Y: &str = "br-y";
+/// Scan mode, e.g. "Color", "Gray" or "Lineart".
+pub const MODE: &str = "mode";
+/// Scan source, e.g. "Flatbed" or "ADF".
+pub const SOURCE: &str = "source";
+/// Brightness adjustment.
+pub const BRIGHTNESS: &str = "brightness";
+/// Contrast adjustment.
+pub const CONTRAST: &str = "contrast";
+/// Threshold for lineart scans.
+pub const THRESHOLD: &str = "threshold";
+/// Invert the image.
+pub const NEGATIVE: &str = "negative";
+
+/// Rectangle to acquire, from the `tl-x`, `tl-y`, `br-x` and `br-y` options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanArea {
+    pub tl_x: f64,
+    pub tl_y: f64,
+    pub br_x: f64,
+    pub br_y: f64,
+    /// Unit of the coordinates, either `Unit::MM` or `Unit::Pixel`.
+    pub unit: Unit,
+}
+
+impl ScanArea {
+    pub fn width(&self) -> f64 {
+        self.br_x - self.tl_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.br_y - self.tl_y
+    }
+
+    /// Convert the coordinates to `unit`, using `resolution` in DPI to convert between millimeters and pixels.
+    pub fn convert(&self, unit: Unit, resolution: f64) -> Result<Self> {
+        let factor = match (self.unit, unit) {
+            (from, to) if from == to => 1.0,
+            (Unit::MM, Unit::Pixel) => resolution / MM_PER_INCH,
+            (Unit::Pixel, Unit::MM) => MM_PER_INCH / resolution,
+            _ => return Err(SaneError::Invalid),
+        };
+
+        Ok(Self {
+            tl_x: self.tl_x * factor,
+            tl_y: self.tl_y * factor,
+            br_x: self.br_x * factor,
+            br_y: self.br_y * factor,
+            unit,
+        })
+    }
+}
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Find the option called `name` among `options`
+fn find<'a>(
+    options: impl IntoIterator<Item = Result<OptionDescriptor<'a>>>,
+    name: &str,
+) -> Result<Option<OptionDescriptor<'a>>> {
+    for option in options {
+        let option = option?;
+        if option.name.is_some_and(|n| n.to_bytes() == name.as_bytes()) {
+            return Ok(Some(option));
+        }
+    }
+    Ok(None)
+}
+
+fn number(value: Value) -> Result<f64> {
+    match value {
+        Value::Int(i) if !i.is_empty() => Ok(i[0] as f64),
+        Value::Fixed(f) if !f.is_empty() => Ok(f[0].to_f64()),
+        _ => Err(SaneError::Invalid),
+    }
+}
+
+/// Encode `number` for an option of type `value_type`, rounding it for integer options
+fn number_value(value_type: ValueType, number: f64) -> Result<Value> {
+    match value_type {
+        ValueType::Int => Ok(Value::Int(Box::new([number.round() as i32]))),
+        ValueType::Fixed => Ok(Value::Fixed(Box::new([SaneFixed::from_f64(number)]))),
+        _ => Err(SaneError::Invalid),
+    }
+}
+
+fn string(value: Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.to_string_lossy().into_owned()),
+        _ => Err(SaneError::Invalid),
+    }
+}
+
+fn boolean(value: Value) -> Result<bool> {
+    match value {
+        Value::Bool(b) if !b.is_empty() => Ok(b[0]),
+        _ => Err(SaneError::Invalid),
+    }
+}
+
+fn merge(a: OptionInfo, b: OptionInfo) -> OptionInfo {
+    OptionInfo {
+        inexact: a.inexact || b.inexact,
+        reload_options: a.reload_options || b.reload_options,
+        reload_params: a.reload_params || b.reload_params,
+    }
+}
+
+impl<'sane> Device<'sane> {
+    /// Find an option by name.
+    pub fn find_option(&self, name: &str) -> Result<Option<OptionDescriptor<'_>>> {
+        find(self.options()?, name)
+    }
+
+    fn well_known(&self, name: &'static str) -> Result<OptionDescriptor<'_>> {
+        self.find_option(name)?
+            .ok_or(SaneError::MissingOption(name))
+    }
+
+    fn get_value(&self, name: &'static str) -> Result<Value> {
+        let descriptor = self.well_known(name)?;
+        self.get_option(&descriptor)?
+            .ok_or(SaneError::MissingOption(name))
+    }
+
+    fn get_number(&self, name: &'static str) -> Result<f64> {
+        number(self.get_value(name)?)
+    }
+
+    fn set_number(&self, name: &'static str, number: f64) -> Result<OptionInfo> {
+        let descriptor = self.well_known(name)?;
+        let value = number_value(descriptor.value_type, number)?;
+        Ok(self.set_option(&descriptor, &value)?.info)
+    }
+
+    fn get_string(&self, name: &'static str) -> Result<String> {
+        string(self.get_value(name)?)
+    }
+
+    fn set_string(&self, name: &'static str, string: &str) -> Result<OptionInfo> {
+        let descriptor = self.well_known(name)?;
+        let string = CString::new(string).map_err(|_| SaneError::Invalid)?;
+        let value = Value::String(string.into_boxed_c_str());
+        Ok(self.set_option(&descriptor, &value)?.info)
+    }
+
+    fn get_bool(&self, name: &'static str) -> Result<bool> {
+        boolean(self.get_value(name)?)
+    }
+
+    fn set_bool(&self, name: &'static str, b: bool) -> Result<OptionInfo> {
+        let descriptor = self.well_known(name)?;
+        Ok(self
+            .set_option(&descriptor, &Value::Bool(Box::new([b])))?
+            .info)
+    }
+
+    /// Scan resolution in DPI.
+    pub fn resolution(&self) -> Result<f64> {
+        self.get_number(RESOLUTION)
+    }
+
+    pub fn set_resolution(&self, dpi: f64) -> Result<OptionInfo> {
+        self.set_number(RESOLUTION, dpi)
+    }
+
+    /// Scan mode, e.g. "Color", "Gray" or "Lineart".
+    pub fn mode(&self) -> Result<String> {
+        self.get_string(MODE)
+    }
+
+    pub fn set_mode(&self, mode: &str) -> Result<OptionInfo> {
+        self.set_string(MODE, mode)
+    }
+
+    /// Scan source, e.g. "Flatbed" or "ADF".
+    pub fn source(&self) -> Result<String> {
+        self.get_string(SOURCE)
+    }
+
+    pub fn set_source(&self, source: &str) -> Result<OptionInfo> {
+        self.set_string(SOURCE, source)
+    }
+
+    pub fn preview(&self) -> Result<bool> {
+        self.get_bool(PREVIEW)
+    }
+
+    pub fn set_preview(&self, preview: bool) -> Result<OptionInfo> {
+        self.set_bool(PREVIEW, preview)
+    }
+
+    /// Number of bits per sample.
+    pub fn depth(&self) -> Result<i32> {
+        self.get_number(DEPTH).map(|depth| depth as i32)
+    }
+
+    pub fn set_depth(&self, depth: i32) -> Result<OptionInfo> {
+        self.set_number(DEPTH, depth as f64)
+    }
+
+    pub fn brightness(&self) -> Result<f64> {
+        self.get_number(BRIGHTNESS)
+    }
+
+    pub fn set_brightness(&self, brightness: f64) -> Result<OptionInfo> {
+        self.set_number(BRIGHTNESS, brightness)
+    }
+
+    pub fn contrast(&self) -> Result<f64> {
+        self.get_number(CONTRAST)
+    }
+
+    pub fn set_contrast(&self, contrast: f64) -> Result<OptionInfo> {
+        self.set_number(CONTRAST, contrast)
+    }
+
+    pub fn threshold(&self) -> Result<f64> {
+        self.get_number(THRESHOLD)
+    }
+
+    pub fn set_threshold(&self, threshold: f64) -> Result<OptionInfo> {
+        self.set_number(THRESHOLD, threshold)
+    }
+
+    pub fn negative(&self) -> Result<bool> {
+        self.get_bool(NEGATIVE)
+    }
+
+    pub fn set_negative(&self, negative: bool) -> Result<OptionInfo> {
+        self.set_bool(NEGATIVE, negative)
+    }
+
+    /// The scan area, in the unit the backend uses for it.
+    pub fn scan_area(&self) -> Result<ScanArea> {
+        Ok(ScanArea {
+            tl_x: self.get_number(TL_X)?,
+            tl_y: self.get_number(TL_Y)?,
+            br_x: self.get_number(BR_X)?,
+            br_y: self.get_number(BR_Y)?,
+            unit: self.well_known(TL_X)?.unit,
+        })
+    }
+
+    /// Set the scan area, converting it to the backend's unit at the current resolution if necessary.
+    pub fn set_scan_area(&self, area: &ScanArea) -> Result<OptionInfo> {
+        let unit = self.well_known(TL_X)?.unit;
+        let area = if area.unit == unit {
+            *area
+        } else {
+            area.convert(unit, self.resolution()?)?
+        };
+
+        let mut info = OptionInfo::default();
+        for (name, number) in &[
+            (TL_X, area.tl_x),
+            (TL_Y, area.tl_y),
+            (BR_X, area.br_x),
+            (BR_Y, area.br_y),
+        ] {
+            info = merge(info, self.set_number(name, *number)?);
+        }
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::option_descriptor::{Capabilities, Constraint};
+    use libsane_sys::*;
+    use std::ffi::CStr;
+
+    fn area(tl_x: f64, tl_y: f64, br_x: f64, br_y: f64, unit: Unit) -> ScanArea {
+        ScanArea {
+            tl_x,
+            tl_y,
+            br_x,
+            br_y,
+            unit,
+        }
+    }
+
+    fn assert_close(a: &ScanArea, b: &ScanArea) {
+        assert_eq!(a.unit, b.unit);
+        for (x, y) in &[
+            (a.tl_x, b.tl_x),
+            (a.tl_y, b.tl_y),
+            (a.br_x, b.br_x),
+            (a.br_y, b.br_y),
+        ] {
+            assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn option(name: Option<&'static [u8]>) -> Result<OptionDescriptor<'static>> {
+        Ok(OptionDescriptor {
+            number: 1,
+            name: name.map(|name| CStr::from_bytes_with_nul(name).unwrap()),
+            title: None,
+            description: None,
+            value_type: ValueType::Int,
+            capabilities: Capabilities::from(
+                (SANE_CAP_SOFT_SELECT | SANE_CAP_SOFT_DETECT) as SANE_Int,
+            ),
+            unit: Unit::None,
+            size: std::mem::size_of::<SANE_Word>() as SANE_Int,
+            constraint: Constraint::None,
+        })
+    }
+
+    #[test]
+    fn converts_millimeters_to_pixels() {
+        let inch = area(0.0, 25.4, 25.4, 50.8, Unit::MM);
+        for &dpi in &[75.0, 150.0, 300.0, 600.0, 1200.0] {
+            assert_close(
+                &inch.convert(Unit::Pixel, dpi).unwrap(),
+                &area(0.0, dpi, dpi, 2.0 * dpi, Unit::Pixel),
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_between_millimeters_and_pixels() {
+        let letter = area(3.2, 4.5, 215.9, 279.4, Unit::MM);
+        for &dpi in &[75.0, 100.0, 150.0, 300.0, 600.0, 1200.0, 4800.0] {
+            let pixels = letter.convert(Unit::Pixel, dpi).unwrap();
+            assert_close(&pixels.convert(Unit::MM, dpi).unwrap(), &letter);
+        }
+
+        let pixels = area(10.0, 20.0, 2550.0, 3300.0, Unit::Pixel);
+        let mm = pixels.convert(Unit::MM, 300.0).unwrap();
+        assert!((mm.width() - 2540.0 / 300.0 * 25.4).abs() < 1e-9);
+        assert_close(&mm.convert(Unit::Pixel, 300.0).unwrap(), &pixels);
+    }
+
+    #[test]
+    fn keeps_areas_in_the_same_unit() {
+        let pixels = area(1.0, 2.0, 3.0, 4.0, Unit::Pixel);
+        assert_eq!(pixels.convert(Unit::Pixel, 0.0).unwrap(), pixels);
+    }
+
+    #[test]
+    fn rejects_conversions_to_other_units() {
+        let mm = area(1.0, 2.0, 3.0, 4.0, Unit::MM);
+        assert!(matches!(
+            mm.convert(Unit::DPI, 300.0),
+            Err(SaneError::Invalid)
+        ));
+        assert!(matches!(
+            mm.convert(Unit::Percent, 300.0),
+            Err(SaneError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn finds_options_by_name() {
+        let options = vec![
+            option(None),
+            option(Some(b"mode\0")),
+            option(Some(b"source\0")),
+        ];
+        let found = find(options, SOURCE).unwrap().unwrap();
+        assert_eq!(found.name.unwrap().to_bytes(), b"source");
+    }
+
+    #[test]
+    fn reports_missing_options() {
+        let options = vec![option(None), option(Some(b"mode\0"))];
+        assert!(find(options, RESOLUTION).unwrap().is_none());
+        assert!(find(vec![option(None), Err(SaneError::Io)], MODE).is_err());
+    }
+
+    #[test]
+    fn reads_numbers_from_int_and_fixed_options() {
+        assert_eq!(number(Value::Int(Box::new([300]))).unwrap(), 300.0);
+        assert_eq!(
+            number(Value::Fixed(Box::new([SaneFixed::from_f64(-2.5)]))).unwrap(),
+            -2.5
+        );
+    }
+
+    #[test]
+    fn rejects_numbers_of_other_types() {
+        for value in [
+            Value::Int(Box::new([])),
+            Value::Bool(Box::new([true])),
+            Value::String(Box::from(CStr::from_bytes_with_nul(b"300\0").unwrap())),
+        ] {
+            assert!(matches!(number(value), Err(SaneError::Invalid)));
+        }
+    }
+
+    #[test]
+    fn encodes_numbers_for_the_option_type() {
+        assert_eq!(
+            number_value(ValueType::Int, 299.6).unwrap(),
+            Value::Int(Box::new([300]))
+        );
+        assert_eq!(
+            number_value(ValueType::Fixed, 12.25).unwrap(),
+            Value::Fixed(Box::new([SaneFixed::from_f64(12.25)]))
+        );
+        for &value_type in &[ValueType::Bool, ValueType::String, ValueType::Button] {
+            assert!(matches!(
+                number_value(value_type, 1.0),
+                Err(SaneError::Invalid)
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_strings_and_bools_only_from_their_types() {
+        let color = Value::String(Box::from(CStr::from_bytes_with_nul(b"Color\0").unwrap()));
+        assert_eq!(string(color.clone()).unwrap(), "Color");
+        assert!(matches!(
+            string(Value::Int(Box::new([1]))),
+            Err(SaneError::Invalid)
+        ));
+
+        assert!(boolean(Value::Bool(Box::new([true]))).unwrap());
+        assert!(matches!(boolean(color), Err(SaneError::Invalid)));
+        assert!(matches!(
+            boolean(Value::Bool(Box::new([]))),
+            Err(SaneError::Invalid)
+        ));
+    }
+}